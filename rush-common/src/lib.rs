@@ -0,0 +1,5 @@
+#![cfg_attr(not(test), no_std)]
+
+pub mod rush_config_store;
+pub mod rush_error;
+pub mod rush_response;
@@ -0,0 +1,603 @@
+/*
+Flash Layout:
+The store spans a number of erase sectors, only one of which is active at a time.
+Every sector starts with a header, followed by the entries appended to it:
+
+sector header: magic (u32 le) | sequence number (u32 le)
+entry:         key length (u8) | value length (u8) | checksum (u16 le) | key | value | padding
+
+The active sector is the one with the highest sequence number.
+Setting a key appends an entry, an entry with an empty value removes its key - the last entry for a key wins.
+Once the active sector is full, the live entries are copied into the next sector, which then becomes the
+active one. Sectors are used in turns, so each of them is erased equally often.
+Entries are written before the header of their sector, so a sector only becomes active once it is complete.
+A torn entry fails its checksum - the sector is then treated as full and compacted on the next write.
+
+The fixed ssid/password record that older firmware kept at the start of the partition (magic "rusc") is not
+migrated: it is no valid sector, so the partition is formatted on the first boot and the settings start
+over from their defaults.
+*/
+
+use crate::rush_error::RushError;
+
+use embedded_storage::nor_flash::NorFlash;
+use heapless::{String, Vec};
+
+pub const MAX_KEY_LEN: usize = 16;
+pub const MAX_VALUE_LEN: usize = 64;
+pub const MAX_ENTRIES: usize = 64; // settings and pin aliases
+
+const SECTOR_MAGIC: u32 = 0x7275_736B; // "rusk"
+const SECTOR_HEADER_SIZE: u32 = 8;
+const ENTRY_HEADER_SIZE: usize = 4;
+const ERASED: u8 = 0xFF;
+
+// header, key, value and enough room for padding to any sensible write size
+const MAX_ENTRY_SIZE: usize = ENTRY_HEADER_SIZE + MAX_KEY_LEN + MAX_VALUE_LEN + 16;
+
+// key/value store on nor flash - generic over the flash, so the tests below run it against a ram mock
+pub struct RushConfigStore<F: NorFlash> {
+    flash: F,
+    offset: u32,
+    sector_count: u32,
+    active_sector: u32,
+    sequence: u32,
+    write_offset: u32, // relative to the start of the active sector
+    entries: Vec<(String<MAX_KEY_LEN>, String<MAX_VALUE_LEN>), MAX_ENTRIES>,
+}
+
+impl<F: NorFlash> RushConfigStore<F> {
+    // reads the entries of the active sector - formats the store if no sector is valid
+    pub fn mount(flash: F, offset: u32, size: u32) -> Result<Self, RushError> {
+        let sector_count = size / F::ERASE_SIZE as u32;
+        if sector_count < 2 || offset % F::ERASE_SIZE as u32 != 0 {
+            return Err(RushError::Storage(
+                "config store needs at least two aligned sectors",
+            ));
+        }
+
+        let mut store = RushConfigStore {
+            flash,
+            offset,
+            sector_count,
+            active_sector: 0,
+            sequence: 0,
+            write_offset: SECTOR_HEADER_SIZE,
+            entries: Vec::new(),
+        };
+
+        let mut active = None;
+        for sector in 0..sector_count {
+            let mut header = [0u8; SECTOR_HEADER_SIZE as usize];
+            store.read(store.sector_address(sector), &mut header)?;
+            let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+            let sequence = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+            if magic != SECTOR_MAGIC || sequence == u32::MAX {
+                continue;
+            }
+            match active {
+                Some((_, active_sequence)) if active_sequence >= sequence => (),
+                _ => active = Some((sector, sequence)),
+            }
+        }
+
+        match active {
+            None => {
+                log::info!("no config store found in flash - formatting");
+                store.start_sector(0, 1)?;
+            }
+            Some((sector, sequence)) => {
+                store.active_sector = sector;
+                store.sequence = sequence;
+                store.read_entries()?;
+            }
+        }
+        Ok(store)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(entry_key, _)| entry_key == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), RushError> {
+        if key.is_empty() || key.len() > MAX_KEY_LEN {
+            return Err(RushError::InvalidValue("key must be 1 to 16 bytes long"));
+        }
+        if value.is_empty() || value.len() > MAX_VALUE_LEN {
+            return Err(RushError::InvalidValue("value must be 1 to 64 bytes long"));
+        }
+        match self.get(key) {
+            Some(old_value) if old_value == value => return Ok(()), // spare the flash
+            None if self.entries.len() == MAX_ENTRIES => {
+                return Err(RushError::Storage("config store holds too many keys"))
+            }
+            _ => (),
+        }
+        // compacting has to fit every live entry into a single sector
+        let live_size: u32 = self
+            .entries
+            .iter()
+            .filter(|(entry_key, _)| entry_key != key)
+            .map(|(entry_key, entry_value)| Self::entry_size(entry_key.len(), entry_value.len()))
+            .sum();
+        if SECTOR_HEADER_SIZE + live_size + Self::entry_size(key.len(), value.len())
+            > F::ERASE_SIZE as u32
+        {
+            return Err(RushError::Storage("config store is full"));
+        }
+
+        self.append(key, value)?;
+        self.cache(key, value);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<(), RushError> {
+        if self.get(key).is_none() {
+            return Ok(());
+        }
+
+        self.append(key, "")?;
+        self.entries.retain(|(entry_key, _)| entry_key != key);
+        Ok(())
+    }
+
+    // erases every sector and starts over with an empty store
+    pub fn clear(&mut self) -> Result<(), RushError> {
+        for sector in 0..self.sector_count {
+            self.erase(sector)?;
+        }
+        self.entries.clear();
+        self.start_sector(0, self.sequence.wrapping_add(1))
+    }
+
+    fn sector_address(&self, sector: u32) -> u32 {
+        self.offset + sector * F::ERASE_SIZE as u32
+    }
+
+    // entries are padded so every write starts and ends on a write and read boundary
+    fn entry_size(key_len: usize, value_len: usize) -> u32 {
+        let align = F::WRITE_SIZE.max(F::READ_SIZE);
+        let size = ENTRY_HEADER_SIZE + key_len + value_len;
+        ((size + align - 1) / align * align) as u32
+    }
+
+    fn read_entries(&mut self) -> Result<(), RushError> {
+        let sector_address = self.sector_address(self.active_sector);
+        let mut buffer = [ERASED; MAX_ENTRY_SIZE];
+        self.write_offset = SECTOR_HEADER_SIZE;
+
+        while self.write_offset + ENTRY_HEADER_SIZE as u32 <= F::ERASE_SIZE as u32 {
+            let address = sector_address + self.write_offset;
+            self.read(address, &mut buffer[..ENTRY_HEADER_SIZE])?;
+            let (key_len, value_len) = (buffer[0] as usize, buffer[1] as usize);
+            if buffer[..ENTRY_HEADER_SIZE] == [ERASED; ENTRY_HEADER_SIZE] {
+                return Ok(()); // end of the written part
+            }
+
+            let entry_size = Self::entry_size(key_len, value_len) as usize;
+            let is_valid = key_len > 0
+                && key_len <= MAX_KEY_LEN
+                && value_len <= MAX_VALUE_LEN
+                && self.write_offset + entry_size as u32 <= F::ERASE_SIZE as u32
+                && {
+                    self.read(address, &mut buffer[..entry_size])?;
+                    let key = &buffer[ENTRY_HEADER_SIZE..ENTRY_HEADER_SIZE + key_len];
+                    let value = &buffer
+                        [ENTRY_HEADER_SIZE + key_len..ENTRY_HEADER_SIZE + key_len + value_len];
+                    u16::from_le_bytes([buffer[2], buffer[3]]) == checksum(key, value)
+                };
+            if !is_valid {
+                // torn write - nothing behind this point can be trusted, so the next write compacts
+                log::warn!("config store: corrupted entry at {:#x}", address);
+                self.write_offset = F::ERASE_SIZE as u32;
+                return Ok(());
+            }
+
+            let key = core::str::from_utf8(&buffer[ENTRY_HEADER_SIZE..ENTRY_HEADER_SIZE + key_len]);
+            let value = core::str::from_utf8(
+                &buffer[ENTRY_HEADER_SIZE + key_len..ENTRY_HEADER_SIZE + key_len + value_len],
+            );
+            match (key, value) {
+                (Ok(key), Ok("")) => self.entries.retain(|(entry_key, _)| entry_key != key),
+                (Ok(key), Ok(value)) => self.cache(key, value),
+                _ => log::warn!(
+                    "config store: skipping entry that is not utf8 at {:#x}",
+                    address
+                ),
+            }
+            self.write_offset += entry_size as u32;
+        }
+        Ok(())
+    }
+
+    fn cache(&mut self, key: &str, value: &str) {
+        let mut new_value = String::new();
+        new_value.push_str(value).ok();
+        match self
+            .entries
+            .iter_mut()
+            .find(|(entry_key, _)| entry_key == key)
+        {
+            Some((_, entry_value)) => *entry_value = new_value,
+            None => {
+                let mut new_key = String::new();
+                new_key.push_str(key).ok();
+                self.entries.push((new_key, new_value)).ok();
+            }
+        }
+    }
+
+    fn append(&mut self, key: &str, value: &str) -> Result<(), RushError> {
+        let entry_size = Self::entry_size(key.len(), value.len());
+        if self.write_offset + entry_size > F::ERASE_SIZE as u32 {
+            self.compact()?;
+            if self.write_offset + entry_size > F::ERASE_SIZE as u32 {
+                return Err(RushError::Storage("config store is full"));
+            }
+        }
+
+        let address = self.sector_address(self.active_sector) + self.write_offset;
+        self.write_entry(address, key, value)?;
+        self.write_offset += entry_size;
+        Ok(())
+    }
+
+    // copies the live entries into the next sector and makes it the active one
+    fn compact(&mut self) -> Result<(), RushError> {
+        let next_sector = (self.active_sector + 1) % self.sector_count;
+        self.erase(next_sector)?;
+
+        let mut write_offset = SECTOR_HEADER_SIZE;
+        for index in 0..self.entries.len() {
+            let (key, value) = self.entries[index].clone();
+            let address = self.sector_address(next_sector) + write_offset;
+            self.write_entry(address, &key, &value)?;
+            write_offset += Self::entry_size(key.len(), value.len());
+        }
+
+        self.write_header(next_sector, self.sequence.wrapping_add(1))?;
+        self.active_sector = next_sector;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.write_offset = write_offset;
+        Ok(())
+    }
+
+    fn start_sector(&mut self, sector: u32, sequence: u32) -> Result<(), RushError> {
+        self.erase(sector)?;
+        self.write_header(sector, sequence)?;
+        self.active_sector = sector;
+        self.sequence = sequence;
+        self.write_offset = SECTOR_HEADER_SIZE;
+        Ok(())
+    }
+
+    fn write_header(&mut self, sector: u32, sequence: u32) -> Result<(), RushError> {
+        let mut header = [0u8; SECTOR_HEADER_SIZE as usize];
+        header[..4].copy_from_slice(&SECTOR_MAGIC.to_le_bytes());
+        header[4..].copy_from_slice(&sequence.to_le_bytes());
+        let address = self.sector_address(sector);
+        self.write(address, &header)
+    }
+
+    fn write_entry(&mut self, address: u32, key: &str, value: &str) -> Result<(), RushError> {
+        let mut buffer = [ERASED; MAX_ENTRY_SIZE];
+        let value_start = ENTRY_HEADER_SIZE + key.len();
+        buffer[0] = key.len() as u8;
+        buffer[1] = value.len() as u8;
+        buffer[2..4].copy_from_slice(&checksum(key.as_bytes(), value.as_bytes()).to_le_bytes());
+        buffer[ENTRY_HEADER_SIZE..value_start].copy_from_slice(key.as_bytes());
+        buffer[value_start..value_start + value.len()].copy_from_slice(value.as_bytes());
+        self.write(
+            address,
+            &buffer[..Self::entry_size(key.len(), value.len()) as usize],
+        )
+    }
+
+    fn read(&mut self, address: u32, bytes: &mut [u8]) -> Result<(), RushError> {
+        self.flash
+            .read(address, bytes)
+            .map_err(|_| RushError::Storage("could not read from flash"))
+    }
+
+    fn write(&mut self, address: u32, bytes: &[u8]) -> Result<(), RushError> {
+        self.flash
+            .write(address, bytes)
+            .map_err(|_| RushError::Storage("could not write to flash"))
+    }
+
+    fn erase(&mut self, sector: u32) -> Result<(), RushError> {
+        let address = self.sector_address(sector);
+        self.flash
+            .erase(address, address + F::ERASE_SIZE as u32)
+            .map_err(|_| RushError::Storage("could not erase flash"))
+    }
+}
+
+// fletcher-16 - both halves stay below 0xff, so a checksum never looks like erased flash
+fn checksum(key: &[u8], value: &[u8]) -> u16 {
+    let (low, high) = key
+        .iter()
+        .chain(value)
+        .fold((0u16, 0u16), |(low, high), byte| {
+            let low = (low + *byte as u16) % 255;
+            (low, (high + low) % 255)
+        });
+    high << 8 | low
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_storage::nor_flash::{ErrorType, NorFlashErrorKind, ReadNorFlash};
+
+    const SECTOR_SIZE: usize = 256;
+    const SECTORS: usize = 3;
+
+    // nor flash in ram - like the real thing, writes can only clear bits and erasing sets them again
+    struct RamFlash {
+        bytes: [u8; SECTOR_SIZE * SECTORS],
+    }
+
+    impl RamFlash {
+        fn new() -> Self {
+            RamFlash {
+                bytes: [ERASED; SECTOR_SIZE * SECTORS],
+            }
+        }
+    }
+
+    impl ErrorType for RamFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for RamFlash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            match self.bytes.get(offset..offset + bytes.len()) {
+                None => Err(NorFlashErrorKind::OutOfBounds),
+                Some(stored) => {
+                    bytes.copy_from_slice(stored);
+                    Ok(())
+                }
+            }
+        }
+
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+    }
+
+    impl NorFlash for RamFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = SECTOR_SIZE;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            if from as usize % SECTOR_SIZE != 0 || to as usize % SECTOR_SIZE != 0 {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            match self.bytes.get_mut(from as usize..to as usize) {
+                None => Err(NorFlashErrorKind::OutOfBounds),
+                Some(sectors) => {
+                    sectors.fill(ERASED);
+                    Ok(())
+                }
+            }
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            if offset as usize % Self::WRITE_SIZE != 0 || bytes.len() % Self::WRITE_SIZE != 0 {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            let offset = offset as usize;
+            match self.bytes.get_mut(offset..offset + bytes.len()) {
+                None => Err(NorFlashErrorKind::OutOfBounds),
+                Some(stored) => {
+                    for (stored, byte) in stored.iter_mut().zip(bytes) {
+                        *stored &= byte;
+                    }
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    fn mount(flash: &mut RamFlash) -> RushConfigStore<&mut RamFlash> {
+        RushConfigStore::mount(flash, 0, (SECTOR_SIZE * SECTORS) as u32).unwrap()
+    }
+
+    fn entries<'a>(store: &'a RushConfigStore<&mut RamFlash>) -> std::vec::Vec<(&'a str, &'a str)> {
+        store.iter().collect()
+    }
+
+    #[test]
+    fn mount_formats_erased_flash() {
+        let mut flash = RamFlash::new();
+        let store = mount(&mut flash);
+        assert_eq!(entries(&store), []);
+        assert_eq!((store.active_sector, store.sequence), (0, 1));
+        drop(store);
+
+        assert_eq!(flash.bytes[..4], SECTOR_MAGIC.to_le_bytes());
+        let store = mount(&mut flash);
+        assert_eq!((store.active_sector, store.sequence), (0, 1));
+    }
+
+    #[test]
+    fn mount_needs_two_aligned_sectors() {
+        let mut flash = RamFlash::new();
+        assert!(RushConfigStore::mount(&mut flash, 0, SECTOR_SIZE as u32).is_err());
+        assert!(RushConfigStore::mount(&mut flash, 4, 2 * SECTOR_SIZE as u32).is_err());
+    }
+
+    #[test]
+    fn mount_drops_the_old_fixed_record() {
+        let mut flash = RamFlash::new();
+        flash.bytes[..4].copy_from_slice(&0x7275_7363u32.to_le_bytes()); // "rusc"
+        flash.bytes[4] = 4;
+        flash.bytes[5..9].copy_from_slice(b"test");
+        let store = mount(&mut flash);
+        assert_eq!(entries(&store), []);
+    }
+
+    #[test]
+    fn entries_survive_a_remount() {
+        let mut flash = RamFlash::new();
+        let mut store = mount(&mut flash);
+        store.set("ssid", "lab").unwrap();
+        store.set("port", "2000").unwrap();
+        store.set("ssid", "workshop").unwrap();
+        store.set("a.led", "gpio.21").unwrap();
+        store.remove("port").unwrap();
+        drop(store);
+
+        let store = mount(&mut flash);
+        assert_eq!(
+            entries(&store),
+            [("ssid", "workshop"), ("a.led", "gpio.21")]
+        );
+        assert_eq!(store.get("port"), None);
+    }
+
+    #[test]
+    fn set_rejects_bad_lengths() {
+        let mut flash = RamFlash::new();
+        let mut store = mount(&mut flash);
+        assert!(store.set("", "value").is_err());
+        assert!(store.set("a_key_that_is_too_long", "value").is_err());
+        assert!(store.set("key", "").is_err());
+        assert!(store.set("key", &"x".repeat(MAX_VALUE_LEN + 1)).is_err());
+        assert_eq!(entries(&store), []);
+    }
+
+    #[test]
+    fn compaction_moves_the_live_entries_to_the_next_sector() {
+        let mut flash = RamFlash::new();
+        let mut store = mount(&mut flash);
+        store.set("ssid", "lab").unwrap();
+        let mut counter = 0;
+        while store.active_sector == 0 {
+            counter += 1;
+            store.set("port", &counter.to_string()).unwrap();
+        }
+        assert_eq!((store.active_sector, store.sequence), (1, 2));
+        drop(store);
+
+        let store = mount(&mut flash);
+        assert_eq!((store.active_sector, store.sequence), (1, 2));
+        assert_eq!(
+            entries(&store),
+            [("ssid", "lab"), ("port", counter.to_string().as_str())]
+        );
+    }
+
+    #[test]
+    fn sectors_are_used_in_turns() {
+        let mut flash = RamFlash::new();
+        let mut store = mount(&mut flash);
+        store.set("ssid", "lab").unwrap();
+        let mut visited = std::vec![store.active_sector];
+        let mut counter = 0;
+        while visited.len() <= SECTORS {
+            counter += 1;
+            store.set("port", &counter.to_string()).unwrap();
+            if *visited.last().unwrap() != store.active_sector {
+                visited.push(store.active_sector);
+            }
+        }
+        assert_eq!(visited, [0, 1, 2, 0]);
+        drop(store);
+
+        // the older sectors still hold valid headers - the highest sequence number wins
+        let store = mount(&mut flash);
+        assert_eq!((store.active_sector, store.sequence), (0, 4));
+        assert_eq!(store.get("ssid"), Some("lab"));
+        assert_eq!(store.get("port"), Some(counter.to_string().as_str()));
+    }
+
+    #[test]
+    fn a_corrupt_entry_hides_everything_behind_it() {
+        let mut flash = RamFlash::new();
+        let mut store = mount(&mut flash);
+        store.set("ssid", "lab").unwrap();
+        let torn_entry = store.write_offset as usize;
+        store.set("port", "2000").unwrap();
+        store.set("address", "10.0.0.1").unwrap();
+        drop(store);
+
+        // a write that was cut short leaves some of the value bits set
+        flash.bytes[torn_entry + ENTRY_HEADER_SIZE + "port".len()] = ERASED;
+        let mut store = mount(&mut flash);
+        assert_eq!(entries(&store), [("ssid", "lab")]);
+        assert_eq!(store.write_offset, SECTOR_SIZE as u32);
+
+        // the sector counts as full, so the next write moves the store on
+        store.set("port", "2001").unwrap();
+        assert_eq!(store.active_sector, 1);
+        drop(store);
+        let store = mount(&mut flash);
+        assert_eq!(entries(&store), [("ssid", "lab"), ("port", "2001")]);
+    }
+
+    #[test]
+    fn a_corrupt_checksum_is_detected() {
+        let mut flash = RamFlash::new();
+        let mut store = mount(&mut flash);
+        let entry = store.write_offset as usize;
+        store.set("ssid", "lab").unwrap();
+        drop(store);
+
+        flash.bytes[entry + 2] &= 0x0f;
+        let store = mount(&mut flash);
+        assert_eq!(entries(&store), []);
+    }
+
+    #[test]
+    fn set_fails_once_the_live_entries_fill_a_sector() {
+        let mut flash = RamFlash::new();
+        let mut store = mount(&mut flash);
+        let value = "x".repeat(MAX_VALUE_LEN);
+        let mut result = Ok(());
+        for key in 0..MAX_ENTRIES {
+            result = store.set(&key.to_string(), &value);
+            if result.is_err() {
+                break;
+            }
+        }
+        assert_eq!(result, Err(RushError::Storage("config store is full")));
+
+        // what was stored before is still there
+        assert_eq!(store.get("0"), Some(value.as_str()));
+    }
+
+    #[test]
+    fn clear_erases_every_sector() {
+        let mut flash = RamFlash::new();
+        let mut store = mount(&mut flash);
+        store.set("ssid", "lab").unwrap();
+        while store.active_sector == 0 {
+            store.set("port", "2000").unwrap();
+            store.set("port", "2001").unwrap();
+        }
+        store.clear().unwrap();
+        assert_eq!(entries(&store), []);
+        drop(store);
+
+        let store = mount(&mut flash);
+        assert_eq!(entries(&store), []);
+        assert_eq!(store.active_sector, 0);
+        assert!(flash.bytes[SECTOR_SIZE..]
+            .iter()
+            .all(|byte| *byte == ERASED));
+    }
+}
@@ -0,0 +1,51 @@
+// everything that can go wrong for a client - the code is sent with every error and never changes its meaning,
+// so clients can react to it. the text says what exactly went wrong and may change
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RushError {
+    NoSuchPin(&'static str), // the pin, channel or bus does not exist
+    WrongMode(&'static str), // the pin or peripheral is not set up for the operation
+    Busy(&'static str),      // used by a bus, a counter or another client - or all units are taken
+    Forbidden(&'static str), // the board protects the pin
+    InvalidValue(&'static str),
+    Hardware(&'static str), // a driver of the hal failed
+    Timeout(&'static str),
+    Storage(&'static str), // the config store in flash failed or is full
+    ParseError {
+        position: usize, // byte offset into the command, counted from 0
+        expected: &'static str,
+    },
+}
+
+impl RushError {
+    pub fn code(&self) -> u8 {
+        match self {
+            RushError::NoSuchPin(_) => 1,
+            RushError::WrongMode(_) => 2,
+            RushError::Busy(_) => 3,
+            RushError::Forbidden(_) => 4,
+            RushError::InvalidValue(_) => 5,
+            RushError::Hardware(_) => 6,
+            RushError::Timeout(_) => 7,
+            RushError::Storage(_) => 8,
+            RushError::ParseError { .. } => 9,
+        }
+    }
+}
+
+impl core::fmt::Display for RushError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RushError::NoSuchPin(text)
+            | RushError::WrongMode(text)
+            | RushError::Busy(text)
+            | RushError::Forbidden(text)
+            | RushError::InvalidValue(text)
+            | RushError::Hardware(text)
+            | RushError::Timeout(text)
+            | RushError::Storage(text) => f.pad(text),
+            RushError::ParseError { position, expected } => {
+                write!(f, "at col {}: expected {}", position, expected)
+            }
+        }
+    }
+}
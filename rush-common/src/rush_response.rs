@@ -0,0 +1,464 @@
+/*
+Responses:
+Every command answers with a RushResponse and every event is reported as one - the message is what a person
+reads, id, value and fields carry the same information as data. The writers below turn a response into one of
+the formats a client can choose with `format`, so text and json always say the same thing:
+
+text: the message, line by line - a client that uses request ids gets `#[req] ` in front of every line of a
+      response and `* ` in front of every line of an event
+      errors read `error [code]: [message]`, followed by `  => [what the error stopped]` for events
+json: one object per line, e.g. {"ok":true,"cmd":"read","id":"adc.3","value":1234,"mv":567}
+      responses without a value carry their message as "text", or as "lines" if it has more than one line
+      errors read {"ok":false,"cmd":"read","code":4,"error":"[message]"} - events have "event" instead of "cmd"
+      an object that does not fit the buffer is replaced by {"ok":false,"error":"response too long"}, as a cut
+      off object could not be parsed
+*/
+
+use crate::rush_error::RushError;
+
+use core::fmt;
+use heapless::Vec;
+
+pub const MAX_FIELDS: usize = 12;
+
+const CONSEQUENCE_PREFIX: &str = "  => "; // e.g. "error 3: pin was claimed\n  => stopped watching gpio.5"
+const EVENT_PREFIX: &str = "* "; // marks events in text, once the client uses request ids
+const JSON_TOO_LONG: &str = "{\"ok\":false,\"error\":\"response too long\"}\n";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RushFormat {
+    Text,
+    Json,
+}
+
+impl RushFormat {
+    // as the client selects it with `format`
+    pub fn name(&self) -> &'static str {
+        match self {
+            RushFormat::Text => "text",
+            RushFormat::Json => "json",
+        }
+    }
+}
+
+// what a response is about
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RushId<'a> {
+    Pin(&'static str, u8), // e.g. Pin("adc", 3) for adc.3
+    Name(&'a str),         // e.g. a config key
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RushValue<'a> {
+    Number(i64),
+    Decimal(i64, u8), // a fixed point number and its decimal places - Decimal(-205, 1) is -20.5
+    Bool(bool),
+    Text(&'a str),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RushResponse<'a> {
+    pub message: &'a str,
+    pub id: Option<RushId<'a>>,
+    pub value: Option<RushValue<'a>>,
+    pub fields: Vec<(&'static str, RushValue<'a>), MAX_FIELDS>,
+    pub error: Option<RushError>, // the message already names the error - this adds its code
+    pub stopped: Option<&'a str>, // what the error ended, e.g. a watch
+}
+
+impl<'a> RushResponse<'a> {
+    pub fn new(message: &'a str) -> Self {
+        RushResponse {
+            message,
+            id: None,
+            value: None,
+            fields: Vec::new(),
+            error: None,
+            stopped: None,
+        }
+    }
+
+    pub fn failed(err: RushError, message: &'a str) -> Self {
+        RushResponse {
+            error: Some(err),
+            ..RushResponse::new(message)
+        }
+    }
+
+    pub fn id(mut self, id: RushId<'a>) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn value(mut self, value: RushValue<'a>) -> Self {
+        self.value = Some(value);
+        self
+    }
+
+    // fields beyond MAX_FIELDS are dropped
+    pub fn field(mut self, key: &'static str, value: RushValue<'a>) -> Self {
+        self.fields.push((key, value)).ok();
+        self
+    }
+
+    pub fn stopped(mut self, consequence: &'a str) -> Self {
+        self.stopped = Some(consequence);
+        self
+    }
+}
+
+// what goes in front of every line in text
+#[derive(Clone, Copy, Debug)]
+pub enum LinePrefix<'r> {
+    None,
+    Request(&'r str),
+    Event,
+}
+
+// writes a response in the text format
+pub struct TextResponse<'r, 'a> {
+    pub response: &'r RushResponse<'a>,
+    pub prefix: LinePrefix<'r>,
+}
+
+impl fmt::Display for TextResponse<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let response = self.response;
+        for (index, line) in response.message.lines().enumerate() {
+            write!(f, "{}", self.prefix)?;
+            if let (0, Some(err)) = (index, response.error) {
+                write!(f, "error {}: ", err.code())?;
+            }
+            writeln!(f, "{}", line)?;
+        }
+        if let Some(stopped) = response.stopped {
+            writeln!(f, "{}{}{}", self.prefix, CONSEQUENCE_PREFIX, stopped)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for LinePrefix<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinePrefix::None => Ok(()),
+            LinePrefix::Request(req) => write!(f, "#{} ", req),
+            LinePrefix::Event => f.write_str(EVENT_PREFIX),
+        }
+    }
+}
+
+// what a json object answers - the name is the command as typed, e.g. read, or the source of an event, e.g. watch
+#[derive(Clone, Copy, Debug)]
+pub enum JsonSource<'r> {
+    Command(&'r str),
+    Event(&'r str),
+}
+
+// writes a response as a single line with one json object
+pub struct JsonResponse<'r, 'a> {
+    pub response: &'r RushResponse<'a>,
+    pub req: Option<&'r str>,
+    pub source: JsonSource<'r>,
+}
+
+impl fmt::Display for JsonResponse<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let response = self.response;
+        write!(f, "{{\"ok\":{}", response.error.is_none())?;
+        if let Some(req) = self.req {
+            write!(f, ",\"req\":\"{}\"", JsonString(req))?;
+        }
+        match self.source {
+            JsonSource::Command(cmd) => write!(f, ",\"cmd\":\"{}\"", JsonString(cmd))?,
+            JsonSource::Event(event) => write!(f, ",\"event\":\"{}\"", JsonString(event))?,
+        }
+
+        if let Some(err) = response.error {
+            write!(
+                f,
+                ",\"code\":{},\"error\":\"{}\"",
+                err.code(),
+                JsonString(response.message.trim_end_matches('\n'))
+            )?;
+            if let Some(stopped) = response.stopped {
+                write!(f, ",\"stopped\":\"{}\"", JsonString(stopped))?;
+            }
+            return f.write_str("}\n");
+        }
+
+        if let Some(id) = response.id {
+            write!(f, ",\"id\":\"{}\"", id)?;
+        }
+        if let Some(value) = response.value {
+            write!(f, ",\"value\":{}", JsonValue(value))?;
+        }
+        for (key, value) in response.fields.iter() {
+            write!(f, ",\"{}\":{}", JsonString(key), JsonValue(*value))?;
+        }
+        // the value already says what the message says
+        if response.value.is_none() {
+            let mut lines = response.message.lines();
+            match (lines.next(), lines.next()) {
+                (None, _) => (),
+                (Some(line), None) => write!(f, ",\"text\":\"{}\"", JsonString(line))?,
+                (Some(_), Some(_)) => {
+                    f.write_str(",\"lines\":[")?;
+                    for (index, line) in response.message.lines().enumerate() {
+                        if index > 0 {
+                            f.write_str(",")?;
+                        }
+                        write!(f, "\"{}\"", JsonString(line))?;
+                    }
+                    f.write_str("]")?;
+                }
+            }
+        }
+        f.write_str("}\n")
+    }
+}
+
+impl JsonResponse<'_, '_> {
+    // the whole object or JSON_TOO_LONG - never a part of the object
+    pub fn write<'b>(&self, buffer: &'b mut [u8]) -> &'b str {
+        let mut writer = BufferWriter { buffer, len: 0 };
+        let len = match fmt::write(&mut writer, format_args!("{}", self)) {
+            Ok(()) => writer.len,
+            Err(_) => {
+                // buffers are much larger than this line, but a cut off line is still better than a panic
+                let len = JSON_TOO_LONG.len().min(writer.buffer.len());
+                writer.buffer[..len].copy_from_slice(&JSON_TOO_LONG.as_bytes()[..len]);
+                len
+            }
+        };
+        // only whole strs were copied, so the bytes are valid utf-8
+        core::str::from_utf8(&writer.buffer[..len]).unwrap_or_default()
+    }
+}
+
+// fails instead of cutting off what does not fit
+struct BufferWriter<'b> {
+    buffer: &'b mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for BufferWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        match self.buffer.get_mut(self.len..end) {
+            Some(free) => {
+                free.copy_from_slice(s.as_bytes());
+                self.len = end;
+                Ok(())
+            }
+            None => Err(fmt::Error),
+        }
+    }
+}
+
+impl fmt::Display for RushId<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RushId::Pin(kind, pin) => write!(f, "{}.{}", kind, pin),
+            RushId::Name(name) => f.pad(name),
+        }
+    }
+}
+
+// numbers and booleans as they are, text as a string
+struct JsonValue<'a>(RushValue<'a>);
+
+impl fmt::Display for JsonValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            RushValue::Number(number) => write!(f, "{}", number),
+            RushValue::Decimal(number, 0) => write!(f, "{}", number),
+            RushValue::Decimal(number, places) => {
+                let scale = 10u64.pow(places as u32);
+                write!(
+                    f,
+                    "{}{}.{:0width$}",
+                    if number < 0 { "-" } else { "" },
+                    number.unsigned_abs() / scale,
+                    number.unsigned_abs() % scale,
+                    width = places as usize
+                )
+            }
+            RushValue::Bool(state) => write!(f, "{}", state),
+            RushValue::Text(text) => write!(f, "\"{}\"", JsonString(text)),
+        }
+    }
+}
+
+// the content of a json string, without the quotes
+pub struct JsonString<'a>(pub &'a str);
+
+impl fmt::Display for JsonString<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if (c as u32) < 0x20 || c == '\u{7f}' => write!(f, "\\u{:04x}", c as u32)?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RushFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(response: &RushResponse, prefix: LinePrefix) -> String {
+        TextResponse { response, prefix }.to_string()
+    }
+
+    fn json(response: &RushResponse, req: Option<&str>, source: JsonSource) -> String {
+        JsonResponse {
+            response,
+            req,
+            source,
+        }
+        .to_string()
+    }
+
+    fn adc_reading() -> RushResponse<'static> {
+        RushResponse::new("adc.3 = 1234 (567mV)\n")
+            .id(RushId::Pin("adc", 3))
+            .value(RushValue::Number(1234))
+            .field("mv", RushValue::Number(567))
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        let escaped = JsonString("say \"hi\"\\\n\r\t\u{1}\u{7f} - grüße").to_string();
+        assert_eq!(escaped, "say \\\"hi\\\"\\\\\\n\\r\\t\\u0001\\u007f - grüße");
+    }
+
+    #[test]
+    fn values_keep_their_type() {
+        let value = |value| JsonValue(value).to_string();
+        assert_eq!(value(RushValue::Number(-42)), "-42");
+        assert_eq!(value(RushValue::Decimal(1234, 1)), "123.4");
+        assert_eq!(value(RushValue::Decimal(-5, 2)), "-0.05");
+        assert_eq!(value(RushValue::Decimal(7, 0)), "7");
+        assert_eq!(value(RushValue::Bool(true)), "true");
+        assert_eq!(value(RushValue::Text("01 a3")), "\"01 a3\"");
+    }
+
+    #[test]
+    fn text_sends_the_message() {
+        let response = adc_reading();
+        assert_eq!(text(&response, LinePrefix::None), "adc.3 = 1234 (567mV)\n");
+        assert_eq!(
+            text(&response, LinePrefix::Request("7")),
+            "#7 adc.3 = 1234 (567mV)\n"
+        );
+    }
+
+    #[test]
+    fn text_prefixes_every_line() {
+        let response = RushResponse::new("gpio.0  floating\ngpio.1  output\n");
+        assert_eq!(
+            text(&response, LinePrefix::Event),
+            "* gpio.0  floating\n* gpio.1  output\n"
+        );
+    }
+
+    #[test]
+    fn text_errors_carry_their_code_and_consequence() {
+        let err = RushError::Busy("pin is now used by a bus or a counter");
+        let response = RushResponse::failed(err, "pin is now used by a bus or a counter")
+            .stopped("stopped watching gpio.5");
+        assert_eq!(
+            text(&response, LinePrefix::Event),
+            "* error 3: pin is now used by a bus or a counter\n*   => stopped watching gpio.5\n"
+        );
+    }
+
+    #[test]
+    fn json_sends_the_value_instead_of_the_message() {
+        let response = adc_reading();
+        assert_eq!(
+            json(&response, None, JsonSource::Command("read")),
+            "{\"ok\":true,\"cmd\":\"read\",\"id\":\"adc.3\",\"value\":1234,\"mv\":567}\n"
+        );
+    }
+
+    #[test]
+    fn json_sends_messages_as_text_or_lines() {
+        let response = RushResponse::new("set gpio.5 pwm = 1000Hz 50%\n")
+            .id(RushId::Pin("gpio", 5))
+            .field("frequency_hz", RushValue::Number(1000));
+        assert_eq!(
+            json(&response, Some("a\"1"), JsonSource::Command("pwm")),
+            "{\"ok\":true,\"req\":\"a\\\"1\",\"cmd\":\"pwm\",\"id\":\"gpio.5\",\"frequency_hz\":1000,\"text\":\"set gpio.5 pwm = 1000Hz 50%\"}\n"
+        );
+
+        let response = RushResponse::new("help\nhelp [command]\n");
+        assert_eq!(
+            json(&response, None, JsonSource::Command("help")),
+            "{\"ok\":true,\"cmd\":\"help\",\"lines\":[\"help\",\"help [command]\"]}\n"
+        );
+    }
+
+    #[test]
+    fn json_errors_carry_their_code() {
+        let err = RushError::Forbidden("pin is connected to the flash");
+        let response = RushResponse::failed(
+            err,
+            "could not write to gpio.27: pin is connected to the flash",
+        );
+        assert_eq!(
+            json(&response, None, JsonSource::Command("write")),
+            "{\"ok\":false,\"cmd\":\"write\",\"code\":4,\"error\":\"could not write to gpio.27: pin is connected to the flash\"}\n"
+        );
+
+        let response = RushResponse::failed(err, "pin is connected to the flash")
+            .stopped("stopped watching gpio.27");
+        assert_eq!(
+            json(&response, None, JsonSource::Event("watch")),
+            "{\"ok\":false,\"event\":\"watch\",\"code\":4,\"error\":\"pin is connected to the flash\",\"stopped\":\"stopped watching gpio.27\"}\n"
+        );
+    }
+
+    #[test]
+    fn json_that_does_not_fit_is_replaced() {
+        let response = adc_reading();
+        let object = JsonResponse {
+            response: &response,
+            req: None,
+            source: JsonSource::Command("read"),
+        };
+        let mut buffer = [0u8; 48];
+        assert_eq!(
+            object.write(&mut buffer),
+            "{\"ok\":false,\"error\":\"response too long\"}\n"
+        );
+        let mut buffer = [0u8; 128];
+        assert_eq!(object.write(&mut buffer), object.to_string());
+    }
+
+    #[test]
+    fn json_events_have_no_cmd() {
+        let response = RushResponse::new("touch.4 = 31000 (touched)\n")
+            .id(RushId::Pin("touch", 4))
+            .value(RushValue::Number(31000))
+            .field("touched", RushValue::Bool(true));
+        assert_eq!(
+            json(&response, None, JsonSource::Event("watch")),
+            "{\"ok\":true,\"event\":\"watch\",\"id\":\"touch.4\",\"value\":31000,\"touched\":true}\n"
+        );
+    }
+}
@@ -1,21 +1,99 @@
 /*
 Command Examples:
-read    [gpio]
+read    [gpio] (as input)
 write   [gpio] [value]
 watch   [gpio]
+watch   [touch] [threshold]
 unwatch [gpio]
+unwatch [touch]
+mode    [gpio] [mode]
+shout   [gpio] [interval]
+unshout [gpio]
+pwm     [gpio] [frequency] [duty]
+pwm     [gpio] off
+i2c open [gpio] [gpio] [frequency]
+i2c close
+i2c scan
+i2c write [address] [bytes]
+i2c read  [address] [length]
+i2c wr    [address] [bytes] [length]
+spi open  [spi] [sck] [mosi] [miso] [cs] [spimode] [frequency]
+spi close [spi]
+spi xfer  [spi] [bytes]
+count   [gpio] start
+count   [gpio] read
+count   [gpio] reset
+count   [gpio] stop
+freq    [gpio] [interval]
+touch   [gpio]
+uart open [gpio] [gpio] [baudrate]
+uart close
+uart bridge
+list
+sys
+board
+board list
+alias [name] = [gpio]
+alias list
+unalias [name]
+format (text|json)
+config get [key]
+config set [key] [text]
+config reset ([key])
+config list
+help
+help [command]
 
-[gpio] is expressed by gpio.[pin]
+[gpio] is expressed by gpio.[pin] - read also accepts adc.[pin] to sample gpio 1 to 10 with the adc, and touch.[pin]
 [value] is expressed by true or false
+commands that use a pin take a trailing force to use pins the board only warns about - e.g. write gpio.0 true force
+[mode] is expressed by floating, pullup, pulldown, pushpull, opendrain or analog
+[frequency] is expressed by a number followed by hz, khz or mhz - a bare number is read as hz
+[duty] is expressed by a percentage from 0 to 100, optionally followed by %
+[interval] is expressed by a number followed by ms or s - a bare number is read as milliseconds
+[address] is expressed by a 7 bit address, either decimal or hexadecimal with 0x
+[bytes] is expressed by hex digits without spaces, two per byte - e.g. 01a3ff
+[length] is expressed by the number of bytes to read, 1 to 32
+[spi] is expressed by spi.2 or spi.3, [sck], [mosi], [miso] and [cs] are gpio pins
+[spimode] is expressed by mode0, mode1, mode2 or mode3
+[touch] is expressed by touch.[pin] - gpio 1 to 14 are touch pads
+[threshold] is expressed by a raw touch value - the pad counts as touched while its value is above
+[baudrate] is expressed by a number of bits per second, e.g. 115200
+while the uart is bridged, every line is sent to the uart - a line with just ~. ends the bridge
+[name] is expressed by a letter followed by up to 13 letters, digits, _ or - - it can be used wherever a pin is expected
+[name] may not be a command, a level, a pin mode, force, input or as
+format json answers every command with one json object per line and sends events the same way
+errors are answered by error [code]: [text] - the codes are listed in rush_error.rs
+a command that can not be parsed is answered by the column and what was expected there - e.g. error 9: at col 6: expected 'gpio.<n>' after 'write'
+every command can be preceded by #[req] - e.g. #7 read gpio.5 is answered by #7 gpio.5 = 1, events start with * then
+[key] is expressed by ssid, password, address, port or board
+[text] is the rest of the line
+help lists these commands, help [command] also explains their arguments - both are taken from COMMANDS
 */
 
-use crate::rush_pin_manager::{RushPinManager, RushPinOperations};
+use crate::rush_board::RushBoard;
+use crate::rush_config::{RushConfig, RushConfigKey, MAX_ALIAS_LEN};
+use crate::rush_error::{format_error, RushError};
+use crate::rush_pin_manager::{RushPinManager, RushPinMode};
+use crate::rush_response::{fmt_split, RushFormat, RushId, RushResponse, RushValue};
+use crate::rush_session::RushSession;
+use crate::rush_system_info::format_system_info;
 
+use core::fmt::Write;
+use core::str::from_utf8;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::Duration;
 use enum_dispatch::enum_dispatch;
 use esp32s3_hal::ehal::digital::v2::PinState;
-use nom::IResult;
+use esp32s3_hal::spi::SpiMode;
+use heapless::String;
+use nom::error::{ContextError, ErrorKind, FromExternalError, ParseError};
+use nom::InputLength;
 use stackfmt::fmt_truncate;
 
+type IResult<I, O> = nom::IResult<I, O, RushParseError<I>>;
+
 #[enum_dispatch]
 #[derive(Debug)]
 pub enum CommandEnum {
@@ -23,28 +101,108 @@ pub enum CommandEnum {
     Watch(WatchCommand),
     Unwatch(UnwatchCommand),
     Write(WriteCommand),
-    /*     Shout(ShoutCommand),
+    Mode(ModeCommand),
+    Shout(ShoutCommand),
     Unshout(UnshoutCommand),
-    List(ListCommand), */
+    Pwm(PwmCommand),
+    I2c(I2cCommand),
+    Spi(SpiCommand),
+    Uart(UartCommand),
+    Count(CountCommand),
+    Freq(FreqCommand),
+    Touch(TouchCommand),
+    List(ListCommand),
+    Sys(SysCommand),
+    Board(BoardCommand),
+    Alias(AliasCommand),
+    Format(FormatCommand),
+    ConfigGet(ConfigGetCommand),
+    ConfigSet(ConfigSetCommand),
+    ConfigReset(ConfigResetCommand),
+    ConfigList(ConfigListCommand),
+    Help(HelpCommand),
 }
 
 #[enum_dispatch(CommandEnum)]
 pub trait Command {
-    fn execute<'a>(&self, fmt_buffer: &'a mut [u8], pin_manager: &mut RushPinManager) -> &'a str;
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        session: &mut RushSession,
+        config: &mut RushConfig,
+        aliases: &mut RushAliases,
+    ) -> RushResponse<'a>;
 }
 
 #[derive(Debug)]
 pub struct ReadCommand {
     pub id: Id,
+    pub as_input: bool,
+    pub force: bool,
 }
 impl Command for ReadCommand {
-    fn execute<'a>(&self, fmt_buffer: &'a mut [u8], pin_manager: &mut RushPinManager) -> &'a str {
-        let Id::Gpio(pin) = self.id;
-        match pin_manager.get_pin(pin).to_input().read_state() {
-            Ok(state) => fmt_truncate(fmt_buffer, format_args!("gpio.{} = {}\n", pin, state as u8)),
-            Err(err) => fmt_truncate(
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        match self.id {
+            Id::Gpio(pin) => match pin_manager.read_pin(pin, self.as_input, self.force) {
+                Ok(state) => RushResponse::new(fmt_truncate(
+                    fmt_buffer,
+                    format_args!("gpio.{} = {}\n", pin, state as u8),
+                ))
+                .id(RushId::Pin("gpio", pin))
+                .value(RushValue::Number(state as i64)),
+                Err(err) => format_error(
+                    fmt_buffer,
+                    err,
+                    format_args!("could not read state of gpio.{}", pin),
+                ),
+            },
+            Id::Adc(pin) => match pin_manager.read_analog_pin(pin, self.force) {
+                Ok((raw, millivolts, true)) => RushResponse::new(fmt_truncate(
+                    fmt_buffer,
+                    format_args!("adc.{} = {} ({}mV)\n", pin, raw, millivolts),
+                ))
+                .id(RushId::Pin("adc", pin))
+                .value(RushValue::Number(raw as i64))
+                .field("mv", RushValue::Number(millivolts as i64)),
+                // chips without calibration in their efuses
+                Ok((raw, millivolts, false)) => RushResponse::new(fmt_truncate(
+                    fmt_buffer,
+                    format_args!("adc.{} = {} ({}mV uncalibrated)\n", pin, raw, millivolts),
+                ))
+                .id(RushId::Pin("adc", pin))
+                .value(RushValue::Number(raw as i64))
+                .field("mv_uncalibrated", RushValue::Number(millivolts as i64)),
+                Err(err) => format_error(
+                    fmt_buffer,
+                    err,
+                    format_args!("could not sample adc.{}", pin),
+                ),
+            },
+            Id::Touch(pin) => match pin_manager.read_touch_pin(pin, self.force) {
+                Ok(value) => RushResponse::new(fmt_truncate(
+                    fmt_buffer,
+                    format_args!("touch.{} = {}\n", pin, value),
+                ))
+                .id(RushId::Pin("touch", pin))
+                .value(RushValue::Number(value as i64)),
+                Err(err) => format_error(
+                    fmt_buffer,
+                    err,
+                    format_args!("could not read touch.{}", pin),
+                ),
+            },
+            Id::Spi(_) => format_error(
                 fmt_buffer,
-                format_args!("error: could not read state of gpio.{}: {}", pin, err),
+                RushError::WrongMode("spi buses can only be used with spi commands"),
+                format_args!("{}", self.id),
             ),
         }
     }
@@ -53,18 +211,81 @@ impl Command for ReadCommand {
 #[derive(Debug)]
 pub struct WatchCommand {
     pub id: Id,
+    pub threshold: Option<u32>, // only for touch pads
+    pub force: bool,
 }
 impl Command for WatchCommand {
-    fn execute<'a>(&self, fmt_buffer: &'a mut [u8], pin_manager: &mut RushPinManager) -> &'a str {
-        let Id::Gpio(pin) = self.id;
-        match pin_manager.watch_pin(pin) {
-            Ok(state) => fmt_truncate(
-                fmt_buffer,
-                format_args!("now watching gpio.{} - current state: {}\n", pin, state),
-            ),
-            Err(err) => fmt_truncate(
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        let pin = match (self.id, self.threshold) {
+            (Id::Touch(pin), Some(threshold)) => {
+                return match pin_manager.watch_touch_pin(
+                    pin,
+                    session.client_id,
+                    threshold,
+                    self.force,
+                ) {
+                    Ok((value, touched)) => {
+                        session.watch(pin);
+                        RushResponse::new(fmt_truncate(
+                            fmt_buffer,
+                            format_args!(
+                                "now watching touch.{} - current value: {} ({})\n",
+                                pin,
+                                value,
+                                if touched { "touched" } else { "released" }
+                            ),
+                        ))
+                        .id(RushId::Pin("touch", pin))
+                        .value(RushValue::Number(value as i64))
+                        .field("touched", RushValue::Bool(touched))
+                    }
+                    Err(err) => format_error(
+                        fmt_buffer,
+                        err,
+                        format_args!("could not watch touch.{}", pin),
+                    ),
+                };
+            }
+            (Id::Touch(_), None) => {
+                return format_error(
+                    fmt_buffer,
+                    RushError::InvalidValue("touch pads need a threshold - e.g. 30000"),
+                    format_args!("{}", self.id),
+                )
+            }
+            (_, Some(_)) => {
+                return format_error(
+                    fmt_buffer,
+                    RushError::InvalidValue("only touch pads take a threshold"),
+                    format_args!("{}", self.id),
+                )
+            }
+            (id, None) => match id.gpio() {
+                Ok(pin) => pin,
+                Err(err) => return format_error(fmt_buffer, err, format_args!("{}", self.id)),
+            },
+        };
+        match pin_manager.watch_pin(pin, session.client_id, self.force) {
+            Ok(state) => {
+                session.watch(pin);
+                RushResponse::new(fmt_truncate(
+                    fmt_buffer,
+                    format_args!("now watching gpio.{} - current state: {}\n", pin, state),
+                ))
+                .id(RushId::Pin("gpio", pin))
+                .value(RushValue::Number(state as i64))
+            }
+            Err(err) => format_error(
                 fmt_buffer,
-                format_args!("error: could not watch gpio.{}: {}\n", pin, err),
+                err,
+                format_args!("could not watch gpio.{}", pin),
             ),
         }
     }
@@ -75,13 +296,34 @@ pub struct UnwatchCommand {
     pub id: Id,
 }
 impl Command for UnwatchCommand {
-    fn execute<'a>(&self, fmt_buffer: &'a mut [u8], pin_manager: &mut RushPinManager) -> &'a str {
-        let Id::Gpio(pin) = self.id;
-        match pin_manager.unwatch_pin(pin) {
-            Ok(()) => fmt_truncate(fmt_buffer, format_args!("stopped watching gpio.{}\n", pin)),
-            Err(err) => fmt_truncate(
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        // touch pads are unwatched like any other pin
+        let pin = match self.id {
+            Id::Touch(pin) => pin,
+            id => match id.gpio() {
+                Ok(pin) => pin,
+                Err(err) => return format_error(fmt_buffer, err, format_args!("{}", self.id)),
+            },
+        };
+        match pin_manager.unwatch_pin(pin, session.client_id) {
+            Ok(()) => {
+                session.unwatch(pin);
+                RushResponse::new(fmt_truncate(
+                    fmt_buffer,
+                    format_args!("stopped watching {}\n", self.id),
+                ))
+            }
+            Err(err) => format_error(
                 fmt_buffer,
-                format_args!("error: could not unwatch gpio.{}: {}", pin, err),
+                err,
+                format_args!("could not unwatch {}", self.id),
             ),
         }
     }
@@ -91,30 +333,1282 @@ impl Command for UnwatchCommand {
 pub struct WriteCommand {
     pub id: Id,
     pub value: Value,
+    pub force: bool,
 }
 impl Command for WriteCommand {
-    fn execute<'a>(&self, fmt_buffer: &'a mut [u8], pin_manager: &mut RushPinManager) -> &'a str {
-        let (Id::Gpio(pin), Value::Gpio(b)) = (&self.id, &self.value);
-        match pin_manager.get_pin(*pin).to_output().set_state(if *b {
-            PinState::High
-        } else {
-            PinState::Low
-        }) {
-            Ok(_) => fmt_truncate(
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        let pin = match self.id.gpio() {
+            Ok(pin) => pin,
+            Err(err) => return format_error(fmt_buffer, err, format_args!("{}", self.id)),
+        };
+        let Value::Gpio(b) = &self.value;
+        match pin_manager.write_pin(
+            pin,
+            if *b { PinState::High } else { PinState::Low },
+            self.force,
+        ) {
+            Ok(_) => RushResponse::new(fmt_truncate(
                 fmt_buffer,
                 format_args!("set gpio.{} = {}\n", pin, *b as u8),
+            ))
+            .id(RushId::Pin("gpio", pin))
+            .value(RushValue::Number(*b as i64)),
+            Err(err) => format_error(
+                fmt_buffer,
+                err,
+                format_args!("could not write to gpio.{}", pin),
             ),
-            Err(err) => fmt_truncate(
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ModeCommand {
+    pub id: Id,
+    pub mode: RushPinMode,
+    pub force: bool,
+}
+impl Command for ModeCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        let pin = match self.id.gpio() {
+            Ok(pin) => pin,
+            Err(err) => return format_error(fmt_buffer, err, format_args!("{}", self.id)),
+        };
+        match pin_manager.set_pin_mode(pin, self.mode, self.force) {
+            Ok(()) => {
+                let (message, rest) = fmt_split(
+                    fmt_buffer,
+                    format_args!("set gpio.{} mode = {}\n", pin, self.mode),
+                );
+                RushResponse::new(message)
+                    .id(RushId::Pin("gpio", pin))
+                    .field(
+                        "mode",
+                        RushValue::Text(fmt_truncate(rest, format_args!("{}", self.mode))),
+                    )
+            }
+            Err(err) => format_error(
                 fmt_buffer,
-                format_args!("error: could not write to gpio.{}: {}\n", pin, err),
+                err,
+                format_args!("could not set mode of gpio.{}", pin),
             ),
         }
     }
 }
 
 #[derive(Debug)]
+pub struct ShoutCommand {
+    pub id: Id,
+    pub interval: Duration,
+    pub force: bool,
+}
+impl Command for ShoutCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        let pin = match self.id.gpio() {
+            Ok(pin) => pin,
+            Err(err) => return format_error(fmt_buffer, err, format_args!("{}", self.id)),
+        };
+        let shout_result = match pin_manager.read_pin(pin, false, self.force) {
+            Ok(state) => session.shout(pin, self.interval).map(|()| state),
+            Err(err) => Err(err),
+        };
+        match shout_result {
+            Ok(state) => RushResponse::new(fmt_truncate(
+                fmt_buffer,
+                format_args!(
+                    "now shouting gpio.{} every {}ms - current state: {}\n",
+                    pin,
+                    self.interval.as_millis(),
+                    state
+                ),
+            ))
+            .id(RushId::Pin("gpio", pin))
+            .value(RushValue::Number(state as i64))
+            .field(
+                "interval_ms",
+                RushValue::Number(self.interval.as_millis() as i64),
+            ),
+            Err(err) => format_error(
+                fmt_buffer,
+                err,
+                format_args!("could not shout gpio.{}", pin),
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnshoutCommand {
+    pub id: Id,
+}
+impl Command for UnshoutCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        _pin_manager: &mut RushPinManager,
+        session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        let pin = match self.id.gpio() {
+            Ok(pin) => pin,
+            Err(err) => return format_error(fmt_buffer, err, format_args!("{}", self.id)),
+        };
+        match session.unshout(pin) {
+            Ok(()) => RushResponse::new(fmt_truncate(
+                fmt_buffer,
+                format_args!("stopped shouting gpio.{}\n", pin),
+            ))
+            .id(RushId::Pin("gpio", pin)),
+            Err(err) => format_error(
+                fmt_buffer,
+                err,
+                format_args!("could not unshout gpio.{}", pin),
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PwmCommand {
+    pub id: Id,
+    pub setting: PwmSetting,
+}
+impl Command for PwmCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        let pin = match self.id.gpio() {
+            Ok(pin) => pin,
+            Err(err) => return format_error(fmt_buffer, err, format_args!("{}", self.id)),
+        };
+        match self.setting {
+            PwmSetting::On {
+                frequency_hz,
+                duty_pct,
+                force,
+            } => match pin_manager.start_pwm(pin, frequency_hz, duty_pct, force) {
+                Ok(()) => RushResponse::new(fmt_truncate(
+                    fmt_buffer,
+                    format_args!("set gpio.{} pwm = {}Hz {}%\n", pin, frequency_hz, duty_pct),
+                ))
+                .id(RushId::Pin("gpio", pin))
+                .field("frequency_hz", RushValue::Number(frequency_hz as i64))
+                .field("duty_pct", RushValue::Number(duty_pct as i64)),
+                Err(err) => format_error(
+                    fmt_buffer,
+                    err,
+                    format_args!("could not start pwm on gpio.{}", pin),
+                ),
+            },
+            PwmSetting::Off => match pin_manager.stop_pwm(pin) {
+                Ok(()) => RushResponse::new(fmt_truncate(
+                    fmt_buffer,
+                    format_args!("stopped pwm on gpio.{}\n", pin),
+                ))
+                .id(RushId::Pin("gpio", pin)),
+                Err(err) => format_error(
+                    fmt_buffer,
+                    err,
+                    format_args!("could not stop pwm on gpio.{}", pin),
+                ),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct I2cCommand {
+    pub operation: I2cOperation,
+}
+impl Command for I2cCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        match &self.operation {
+            I2cOperation::Open {
+                sda,
+                scl,
+                frequency_hz,
+                force,
+            } => {
+                let (sda, scl) = match (sda.gpio(), scl.gpio()) {
+                    (Ok(sda), Ok(scl)) => (sda, scl),
+                    (Err(err), _) | (_, Err(err)) => {
+                        return format_error(
+                            fmt_buffer,
+                            err,
+                            format_args!("could not open i2c bus"),
+                        )
+                    }
+                };
+                match pin_manager.i2c_open(sda, scl, *frequency_hz, *force) {
+                    Ok(()) => RushResponse::new(fmt_truncate(
+                        fmt_buffer,
+                        format_args!(
+                            "opened i2c bus - sda = gpio.{}, scl = gpio.{}, {}Hz\n",
+                            sda, scl, frequency_hz
+                        ),
+                    ))
+                    .field("sda", RushValue::Number(sda as i64))
+                    .field("scl", RushValue::Number(scl as i64))
+                    .field("frequency_hz", RushValue::Number(*frequency_hz as i64)),
+                    Err(err) => {
+                        format_error(fmt_buffer, err, format_args!("could not open i2c bus"))
+                    }
+                }
+            }
+            I2cOperation::Close => match pin_manager.i2c_close() {
+                Ok(()) => RushResponse::new("closed i2c bus\n"),
+                Err(err) => format_error(fmt_buffer, err, format_args!("could not close i2c bus")),
+            },
+            I2cOperation::Scan => match pin_manager.i2c_scan() {
+                Ok(addresses) => {
+                    let (value, rest) =
+                        fmt_split(fmt_buffer, format_args!("{:#}", HexBytes(&addresses)));
+                    let message = fmt_truncate(
+                        rest,
+                        format_args!(
+                            "found {} i2c devices:{}{}\n",
+                            addresses.len(),
+                            if addresses.is_empty() { "" } else { " " },
+                            value
+                        ),
+                    );
+                    RushResponse::new(message)
+                        .value(RushValue::Text(value))
+                        .field("count", RushValue::Number(addresses.len() as i64))
+                }
+                Err(err) => format_error(fmt_buffer, err, format_args!("could not scan i2c bus")),
+            },
+            I2cOperation::Transfer {
+                address,
+                write,
+                read_len,
+            } => {
+                let mut read = [0u8; MAX_TRANSFER_BYTES];
+                let read = &mut read[..*read_len as usize];
+                if let Err(err) = pin_manager.i2c_transfer(*address, write, read) {
+                    return format_error(
+                        fmt_buffer,
+                        err,
+                        format_args!("i2c transfer with {:#04x} failed", address),
+                    );
+                }
+                if read.is_empty() {
+                    return RushResponse::new(fmt_truncate(
+                        fmt_buffer,
+                        format_args!("wrote {} bytes to i2c {:#04x}\n", write.len(), address),
+                    ))
+                    .field("address", RushValue::Number(*address as i64))
+                    .field("written", RushValue::Number(write.len() as i64));
+                }
+                let (value, rest) = fmt_split(fmt_buffer, format_args!("{}", HexBytes(read)));
+                let message =
+                    fmt_truncate(rest, format_args!("i2c {:#04x} = {}\n", address, value));
+                RushResponse::new(message)
+                    .value(RushValue::Text(value))
+                    .field("address", RushValue::Number(*address as i64))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SpiCommand {
+    pub bus: Id,
+    pub operation: SpiOperation,
+}
+impl Command for SpiCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        let bus = match self.bus.spi() {
+            Ok(bus) => bus,
+            Err(err) => return format_error(fmt_buffer, err, format_args!("{}", self.bus)),
+        };
+        match &self.operation {
+            SpiOperation::Open {
+                pins,
+                mode,
+                frequency_hz,
+                force,
+            } => {
+                let mut gpios = [0u8; 4];
+                for (gpio, id) in gpios.iter_mut().zip(pins) {
+                    *gpio = match id.gpio() {
+                        Ok(pin) => pin,
+                        Err(err) => {
+                            return format_error(
+                                fmt_buffer,
+                                err,
+                                format_args!("could not open spi.{}: {}", bus, id),
+                            )
+                        }
+                    };
+                }
+                match pin_manager.spi_open(bus, gpios, *mode, *frequency_hz, *force) {
+                    Ok(()) => RushResponse::new(fmt_truncate(
+                        fmt_buffer,
+                        format_args!(
+                            "opened spi.{} - sck = gpio.{}, mosi = gpio.{}, miso = gpio.{}, cs = gpio.{}, {}, {}Hz\n",
+                            bus,
+                            gpios[0],
+                            gpios[1],
+                            gpios[2],
+                            gpios[3],
+                            spi_mode_name(mode),
+                            frequency_hz
+                        ),
+                    ))
+                    .id(RushId::Pin("spi", bus))
+                    .field("sck", RushValue::Number(gpios[0] as i64))
+                    .field("mosi", RushValue::Number(gpios[1] as i64))
+                    .field("miso", RushValue::Number(gpios[2] as i64))
+                    .field("cs", RushValue::Number(gpios[3] as i64))
+                    .field("mode", RushValue::Text(spi_mode_name(mode)))
+                    .field("frequency_hz", RushValue::Number(*frequency_hz as i64)),
+                    Err(err) => format_error(fmt_buffer, err, format_args!("could not open spi.{}", bus)),
+                }
+            }
+            SpiOperation::Close => match pin_manager.spi_close(bus) {
+                Ok(()) => RushResponse::new(fmt_truncate(
+                    fmt_buffer,
+                    format_args!("closed spi.{}\n", bus),
+                ))
+                .id(RushId::Pin("spi", bus)),
+                Err(err) => {
+                    format_error(fmt_buffer, err, format_args!("could not close spi.{}", bus))
+                }
+            },
+            SpiOperation::Transfer { bytes } => {
+                let mut bytes = bytes.clone();
+                if let Err(err) = pin_manager.spi_transfer(bus, &mut bytes) {
+                    return format_error(
+                        fmt_buffer,
+                        err,
+                        format_args!("spi.{} transfer failed", bus),
+                    );
+                }
+                let (value, rest) = fmt_split(fmt_buffer, format_args!("{}", HexBytes(&bytes)));
+                let message = fmt_truncate(rest, format_args!("spi.{} = {}\n", bus, value));
+                RushResponse::new(message)
+                    .id(RushId::Pin("spi", bus))
+                    .value(RushValue::Text(value))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UartCommand {
+    pub operation: UartOperation,
+}
+impl Command for UartCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        match &self.operation {
+            UartOperation::Open {
+                tx,
+                rx,
+                baud_rate,
+                force,
+            } => {
+                let (tx, rx) = match (tx.gpio(), rx.gpio()) {
+                    (Ok(tx), Ok(rx)) => (tx, rx),
+                    (Err(err), _) | (_, Err(err)) => {
+                        return format_error(fmt_buffer, err, format_args!("could not open uart"))
+                    }
+                };
+                match pin_manager.uart_open(session.client_id, tx, rx, *baud_rate, *force) {
+                    // no bridge is lost - this client is not bridging, or its line would have gone to the uart,
+                    // and uart_open() refuses while another client is
+                    Ok(()) => RushResponse::new(fmt_truncate(
+                        fmt_buffer,
+                        format_args!(
+                            "opened uart - tx = gpio.{}, rx = gpio.{}, {} baud\n",
+                            tx, rx, baud_rate
+                        ),
+                    ))
+                    .field("tx", RushValue::Number(tx as i64))
+                    .field("rx", RushValue::Number(rx as i64))
+                    .field("baud_rate", RushValue::Number(*baud_rate as i64)),
+                    Err(err) => format_error(fmt_buffer, err, format_args!("could not open uart")),
+                }
+            }
+            UartOperation::Close => match pin_manager.uart_close() {
+                Ok(()) => {
+                    session.stop_uart_bridge();
+                    RushResponse::new("closed uart\n")
+                }
+                Err(err) => format_error(fmt_buffer, err, format_args!("could not close uart")),
+            },
+            UartOperation::Bridge => match pin_manager.uart_start_bridge(session.client_id) {
+                Ok(()) => {
+                    session.start_uart_bridge();
+                    RushResponse::new("bridging uart - send a line with just ~. to stop\n")
+                }
+                Err(err) => format_error(fmt_buffer, err, format_args!("could not bridge uart")),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CountCommand {
+    pub id: Id,
+    pub action: CountAction,
+}
+impl Command for CountCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        let pin = match self.id.gpio() {
+            Ok(pin) => pin,
+            Err(err) => return format_error(fmt_buffer, err, format_args!("{}", self.id)),
+        };
+        match self.action {
+            CountAction::Start { force } => match pin_manager.count_start(pin, force) {
+                Ok(()) => RushResponse::new(fmt_truncate(
+                    fmt_buffer,
+                    format_args!("counting rising edges of gpio.{}\n", pin),
+                ))
+                .id(RushId::Pin("gpio", pin)),
+                Err(err) => format_error(
+                    fmt_buffer,
+                    err,
+                    format_args!("could not count gpio.{}", pin),
+                ),
+            },
+            CountAction::Read => match pin_manager.count_read(pin) {
+                Ok(count) => RushResponse::new(fmt_truncate(
+                    fmt_buffer,
+                    format_args!("gpio.{} count = {}\n", pin, count),
+                ))
+                .id(RushId::Pin("gpio", pin))
+                .value(RushValue::Number(count as i64)),
+                Err(err) => format_error(
+                    fmt_buffer,
+                    err,
+                    format_args!("could not read count of gpio.{}", pin),
+                ),
+            },
+            CountAction::Reset => match pin_manager.count_reset(pin) {
+                Ok(()) => RushResponse::new(fmt_truncate(
+                    fmt_buffer,
+                    format_args!("reset count of gpio.{}\n", pin),
+                ))
+                .id(RushId::Pin("gpio", pin)),
+                Err(err) => format_error(
+                    fmt_buffer,
+                    err,
+                    format_args!("could not reset count of gpio.{}", pin),
+                ),
+            },
+            CountAction::Stop => match pin_manager.count_stop(pin) {
+                Ok(()) => RushResponse::new(fmt_truncate(
+                    fmt_buffer,
+                    format_args!("stopped counting gpio.{}\n", pin),
+                ))
+                .id(RushId::Pin("gpio", pin)),
+                Err(err) => format_error(
+                    fmt_buffer,
+                    err,
+                    format_args!("could not stop counting gpio.{}", pin),
+                ),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FreqCommand {
+    pub id: Id,
+    pub gate: Duration,
+    pub force: bool,
+}
+impl Command for FreqCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        let pin = match self.id.gpio() {
+            Ok(pin) => pin,
+            Err(err) => return format_error(fmt_buffer, err, format_args!("{}", self.id)),
+        };
+        match session.measure_frequency(pin_manager, pin, self.gate, self.force) {
+            Ok(()) => RushResponse::new(fmt_truncate(
+                fmt_buffer,
+                format_args!(
+                    "measuring frequency of gpio.{} for {}ms\n",
+                    pin,
+                    self.gate.as_millis()
+                ),
+            ))
+            .id(RushId::Pin("gpio", pin))
+            .field("gate_ms", RushValue::Number(self.gate.as_millis() as i64)),
+            Err(err) => format_error(
+                fmt_buffer,
+                err,
+                format_args!("could not measure frequency of gpio.{}", pin),
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TouchCommand {
+    pub id: Id,
+    pub force: bool,
+}
+impl Command for TouchCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        let pin = match self.id {
+            Id::Gpio(pin) | Id::Touch(pin) => pin,
+            _ => {
+                return format_error(
+                    fmt_buffer,
+                    RushError::WrongMode("touch needs a gpio pin"),
+                    format_args!("{}", self.id),
+                )
+            }
+        };
+        match pin_manager.read_touch_pin(pin, self.force) {
+            Ok(value) => RushResponse::new(fmt_truncate(
+                fmt_buffer,
+                format_args!("touch.{} = {}\n", pin, value),
+            ))
+            .id(RushId::Pin("touch", pin))
+            .value(RushValue::Number(value as i64)),
+            Err(err) => format_error(
+                fmt_buffer,
+                err,
+                format_args!("could not read touch.{}", pin),
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ListCommand {}
+impl Command for ListCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        RushResponse::new(pin_manager.list_pins(fmt_buffer))
+    }
+}
+
+#[derive(Debug)]
+pub struct SysCommand {}
+impl Command for SysCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        _pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        format_system_info(fmt_buffer)
+    }
+}
+
+#[derive(Debug)]
+pub struct BoardCommand {
+    pub list: bool, // list all boards instead of the pins of the active one
+}
+impl Command for BoardCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        let mut len = 0;
+        if self.list {
+            for board in RushBoard::ALL {
+                len += fmt_truncate(
+                    &mut fmt_buffer[len..],
+                    format_args!(
+                        "{:<16}{}{}\n",
+                        board.name,
+                        board.description,
+                        if board.name == config.board().name {
+                            " (configured)"
+                        } else {
+                            ""
+                        }
+                    ),
+                )
+                .len();
+            }
+        } else {
+            // the configured board only replaces the active one after a reboot
+            let board = pin_manager.board();
+            len += fmt_truncate(
+                &mut fmt_buffer[len..],
+                format_args!("board = {} - {}\n", board.name, board.description),
+            )
+            .len();
+            for (pin, policy, reason) in board.protected_pins() {
+                len += fmt_truncate(
+                    &mut fmt_buffer[len..],
+                    format_args!("gpio.{:<3}{:<10}{}\n", pin, policy, reason),
+                )
+                .len();
+            }
+        }
+        RushResponse::new(from_utf8(&fmt_buffer[..len]).unwrap_or_default())
+    }
+}
+
+#[derive(Debug)]
+pub struct AliasCommand {
+    pub operation: AliasOperation,
+}
+impl Command for AliasCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        config: &mut RushConfig,
+        aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        match &self.operation {
+            AliasOperation::Set { name, .. } if is_reserved_word(name) => format_error(
+                fmt_buffer,
+                RushError::InvalidValue("the name is a reserved word of a command"),
+                format_args!("could not set alias {}", name),
+            ),
+            AliasOperation::Set { name, id } => {
+                let mut target = String::<16>::new();
+                write!(target, "{}", id).ok();
+                match config.set_alias(name, &target) {
+                    Ok(()) => {
+                        aliases.load(pin_manager.board(), config);
+                        let (name, rest) = fmt_split(fmt_buffer, format_args!("{}", name));
+                        let (target, rest) = fmt_split(rest, format_args!("{}", id));
+                        let message =
+                            fmt_truncate(rest, format_args!("set alias {} = {}\n", name, target));
+                        RushResponse::new(message)
+                            .id(RushId::Name(name))
+                            .value(RushValue::Text(target))
+                    }
+                    Err(err) => format_error(
+                        fmt_buffer,
+                        err,
+                        format_args!("could not set alias {}", name),
+                    ),
+                }
+            }
+            AliasOperation::Remove { name } => match config.remove_alias(name) {
+                Ok(()) => {
+                    aliases.load(pin_manager.board(), config);
+                    let (name, rest) = fmt_split(fmt_buffer, format_args!("{}", name));
+                    let message = fmt_truncate(rest, format_args!("removed alias {}\n", name));
+                    RushResponse::new(message).id(RushId::Name(name))
+                }
+                Err(_) if is_board_alias(pin_manager.board(), name) => format_error(
+                    fmt_buffer,
+                    RushError::WrongMode("board aliases can only be overridden"),
+                    format_args!("could not remove alias {}", name),
+                ),
+                Err(err) => format_error(
+                    fmt_buffer,
+                    err,
+                    format_args!("could not remove alias {}", name),
+                ),
+            },
+            AliasOperation::List => {
+                let mut len = 0;
+                for alias in aliases.aliases.iter() {
+                    len += fmt_truncate(
+                        &mut fmt_buffer[len..],
+                        format_args!(
+                            "{:<15}= {}{}\n",
+                            alias.name,
+                            alias.id,
+                            if alias.from_board { " (board)" } else { "" }
+                        ),
+                    )
+                    .len();
+                }
+                match len {
+                    0 => RushResponse::new("no aliases defined\n"),
+                    _ => RushResponse::new(from_utf8(&fmt_buffer[..len]).unwrap_or_default()),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FormatCommand {
+    pub format: Option<RushFormat>, // None reports the format in use
+}
+impl Command for FormatCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        _pin_manager: &mut RushPinManager,
+        session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        if let Some(format) = self.format {
+            session.format = format;
+        }
+        RushResponse::new(fmt_truncate(
+            fmt_buffer,
+            format_args!("format = {}\n", session.format),
+        ))
+        .value(RushValue::Text(session.format.name()))
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigGetCommand {
+    pub key: RushConfigKey,
+}
+impl Command for ConfigGetCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        _pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        let (value, rest) = fmt_split(
+            fmt_buffer,
+            format_args!("{}", config.display_value(self.key)),
+        );
+        let message = fmt_truncate(rest, format_args!("{} = {}\n", self.key, value));
+        RushResponse::new(message)
+            .id(RushId::Name(self.key.name()))
+            .value(RushValue::Text(value))
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigSetCommand {
+    pub key: RushConfigKey,
+    pub value: String<64>,
+}
+impl Command for ConfigSetCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        _pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        match config.set(self.key, &self.value) {
+            Ok(()) => {
+                let (value, rest) = fmt_split(
+                    fmt_buffer,
+                    format_args!("{}", config.display_value(self.key)),
+                );
+                let message = fmt_truncate(
+                    rest,
+                    format_args!("set {} = {} - takes effect after reboot\n", self.key, value),
+                );
+                RushResponse::new(message)
+                    .id(RushId::Name(self.key.name()))
+                    .value(RushValue::Text(value))
+            }
+            Err(err) => format_error(fmt_buffer, err, format_args!("could not set {}", self.key)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigResetCommand {
+    pub key: Option<RushConfigKey>, // None resets every key
+}
+impl Command for ConfigResetCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        config: &mut RushConfig,
+        aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        match self.key {
+            Some(key) => match config.reset(key) {
+                Ok(()) => {
+                    let (value, rest) =
+                        fmt_split(fmt_buffer, format_args!("{}", config.display_value(key)));
+                    let message = fmt_truncate(
+                        rest,
+                        format_args!("reset {} = {} - takes effect after reboot\n", key, value),
+                    );
+                    RushResponse::new(message)
+                        .id(RushId::Name(key.name()))
+                        .value(RushValue::Text(value))
+                }
+                Err(err) => format_error(fmt_buffer, err, format_args!("could not reset {}", key)),
+            },
+            // the aliases are deleted along with the settings, but unlike those they are gone right away
+            None => match config.reset_all() {
+                Ok(()) => {
+                    aliases.load(pin_manager.board(), config);
+                    RushResponse::new(
+                        "reset all settings to their defaults - takes effect after reboot\n",
+                    )
+                }
+                Err(err) => format_error(fmt_buffer, err, format_args!("could not reset settings")),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigListCommand {}
+impl Command for ConfigListCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        _pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        // every key once more as a field, so json clients get the values without the padding
+        let len = config.list(fmt_buffer).len();
+        let (message, mut rest) = fmt_buffer.split_at_mut(len);
+        let mut response = RushResponse::new(from_utf8(message).unwrap_or_default());
+        for key in RushConfigKey::ALL {
+            let (value, next) = fmt_split(rest, format_args!("{}", config.display_value(key)));
+            response = response.field(key.name(), RushValue::Text(value));
+            rest = next;
+        }
+        response
+    }
+}
+
+#[derive(Debug)]
+pub struct HelpCommand {
+    pub command: Option<&'static str>, // explains a single command instead of listing all
+}
+impl Command for HelpCommand {
+    fn execute<'a>(
+        &self,
+        fmt_buffer: &'a mut [u8],
+        _pin_manager: &mut RushPinManager,
+        _session: &mut RushSession,
+        _config: &mut RushConfig,
+        _aliases: &mut RushAliases,
+    ) -> RushResponse<'a> {
+        let mut len = 0;
+        let command = self
+            .command
+            .and_then(|name| COMMANDS.iter().find(|syntax| syntax.name == name));
+        let command = match command {
+            Some(command) => command,
+            None => {
+                for usage in COMMANDS.iter().flat_map(|syntax| syntax.usage) {
+                    len += fmt_truncate(&mut fmt_buffer[len..], format_args!("{}\n", usage)).len();
+                }
+                return RushResponse::new(from_utf8(&fmt_buffer[..len]).unwrap_or_default());
+            }
+        };
+
+        for usage in command.usage {
+            len += fmt_truncate(&mut fmt_buffer[len..], format_args!("{}\n", usage)).len();
+        }
+        // every argument once, in the order of the usage
+        let mut explained: heapless::Vec<&str, 16> = heapless::Vec::new();
+        for argument in command
+            .usage
+            .iter()
+            .flat_map(|usage| usage.split_whitespace())
+        {
+            let help = match ArgumentHelp::of(argument) {
+                Some(help) if !explained.contains(&argument) => help,
+                _ => continue,
+            };
+            explained.push(argument).ok();
+            len += fmt_truncate(
+                &mut fmt_buffer[len..],
+                format_args!("{} is {}\n", argument, help),
+            )
+            .len();
+        }
+        RushResponse::new(from_utf8(&fmt_buffer[..len]).unwrap_or_default())
+    }
+}
+
+// what an argument in a usage stands for - keywords are listed from the tables the parsers use
+enum ArgumentHelp {
+    Text(&'static str),
+    Values,
+    PinModes,
+    SpiModes,
+    ConfigKeys,
+    Commands,
+}
+
+impl ArgumentHelp {
+    fn of(argument: &str) -> Option<ArgumentHelp> {
+        let help = match argument {
+            "[gpio]" => ArgumentHelp::Text(
+                "gpio.[pin] or an alias - read also accepts adc.[pin] and touch.[pin]",
+            ),
+            "(force)" => ArgumentHelp::Text("optional and uses pins the board only warns about"),
+            "[value]" => ArgumentHelp::Values,
+            "[mode]" => ArgumentHelp::PinModes,
+            "[frequency]" => ArgumentHelp::Text(
+                "a number followed by hz, khz or mhz - a bare number is read as hz",
+            ),
+            "[duty]" => ArgumentHelp::Text("a percentage from 0 to 100, optionally followed by %"),
+            "[interval]" => ArgumentHelp::Text(
+                "a number followed by ms or s - a bare number is read as milliseconds",
+            ),
+            "[address]" => {
+                ArgumentHelp::Text("a 7 bit address, either decimal or hexadecimal with 0x")
+            }
+            "[bytes]" => ArgumentHelp::Text("hex digits, two per byte - e.g. 01a3ff"),
+            "[length]" => ArgumentHelp::Text("the number of bytes to read, 1 to 32"),
+            "[spi]" => ArgumentHelp::Text("spi.2 or spi.3"),
+            "[sck]" | "[mosi]" | "[miso]" | "[cs]" => ArgumentHelp::Text("a gpio pin"),
+            "[spimode]" => ArgumentHelp::SpiModes,
+            "[touch]" => ArgumentHelp::Text("touch.[pin] - gpio 1 to 14 are touch pads"),
+            "[threshold]" => ArgumentHelp::Text(
+                "a raw touch value - the pad counts as touched while its value is above",
+            ),
+            "[baudrate]" => ArgumentHelp::Text("a number of bits per second, e.g. 115200"),
+            "[name]" => ArgumentHelp::Text(
+                "a letter and up to 13 letters, digits, _ or - - not a word the commands already use",
+            ),
+            "[key]" => ArgumentHelp::ConfigKeys,
+            "[text]" => ArgumentHelp::Text("the rest of the line"),
+            "[command]" => ArgumentHelp::Commands,
+            _ => return None,
+        };
+        Some(help)
+    }
+}
+
+impl core::fmt::Display for ArgumentHelp {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ArgumentHelp::Text(text) => f.write_str(text),
+            ArgumentHelp::Values => {
+                let high = GPIO_VALUES.iter().filter(|(_, level)| *level);
+                let low = GPIO_VALUES.iter().filter(|(_, level)| !*level);
+                write_keywords(f, high.map(|(name, _)| *name))?;
+                f.write_str(" for high - ")?;
+                write_keywords(f, low.map(|(name, _)| *name))?;
+                f.write_str(" for low")
+            }
+            ArgumentHelp::PinModes => write_keywords(f, PIN_MODES.iter().map(|(name, _)| *name)),
+            ArgumentHelp::SpiModes => write_keywords(f, SPI_MODES.iter().map(|(name, _)| *name)),
+            ArgumentHelp::ConfigKeys => {
+                write_keywords(f, RushConfigKey::ALL.iter().map(|key| key.name()))
+            }
+            ArgumentHelp::Commands => write_keywords(f, COMMANDS.iter().map(|syntax| syntax.name)),
+        }
+    }
+}
+
+// `a, b or c`
+fn write_keywords<'k>(
+    f: &mut core::fmt::Formatter<'_>,
+    keywords: impl Iterator<Item = &'k str> + Clone,
+) -> core::fmt::Result {
+    let count = keywords.clone().count();
+    for (index, keyword) in keywords.enumerate() {
+        match index {
+            0 => (),
+            _ if index + 1 == count => f.write_str(" or ")?,
+            _ => f.write_str(", ")?,
+        }
+        f.write_str(keyword)?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug)]
 pub enum Id {
     Gpio(u8),
+    Adc(u8),   // gpio number of an adc1 pin
+    Spi(u8),   // number of an spi controller
+    Touch(u8), // gpio number of a touch pad
+}
+
+impl Id {
+    // everything but `read` works on gpio pins only
+    pub fn gpio(&self) -> Result<u8, RushError> {
+        match self {
+            Id::Gpio(pin) => Ok(*pin),
+            Id::Adc(_) => Err(RushError::WrongMode("only read can be used with adc pins")),
+            Id::Spi(_) => Err(RushError::WrongMode(
+                "spi buses can only be used with spi commands",
+            )),
+            Id::Touch(_) => Err(RushError::WrongMode(
+                "touch pads can only be used with touch, watch and unwatch",
+            )),
+        }
+    }
+
+    pub fn spi(&self) -> Result<u8, RushError> {
+        match self {
+            Id::Spi(bus) => Ok(*bus),
+            _ => Err(RushError::WrongMode(
+                "spi commands need an spi bus like spi.2",
+            )),
+        }
+    }
+}
+
+impl core::fmt::Display for Id {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Id::Gpio(pin) => write!(f, "gpio.{}", pin),
+            Id::Adc(pin) => write!(f, "adc.{}", pin),
+            Id::Spi(bus) => write!(f, "spi.{}", bus),
+            Id::Touch(pin) => write!(f, "touch.{}", pin),
+        }
+    }
+}
+
+// names for pins, resolved by id_parser - filled from the board and the aliases in the config by load()
+const MAX_ALIASES: usize = 80;
+
+struct Alias {
+    name: String<MAX_ALIAS_LEN>,
+    id: Id,
+    from_board: bool,
+}
+
+pub type RushAliasesMutex = Mutex<CriticalSectionRawMutex, RushAliases>;
+
+pub struct RushAliases {
+    aliases: heapless::Vec<Alias, MAX_ALIASES>,
+}
+
+impl RushAliases {
+    pub fn new(board: &RushBoard, config: &RushConfig) -> Self {
+        let mut aliases = RushAliases {
+            aliases: heapless::Vec::new(),
+        };
+        aliases.load(board, config);
+        aliases
+    }
+
+    // the aliases of the user replace those of the board with the same name
+    pub fn load(&mut self, board: &RushBoard, config: &RushConfig) {
+        self.aliases.clear();
+        let board_aliases = board.aliases().map(|(name, target)| (name, target, true));
+        let user_aliases = config.aliases().map(|(name, target)| (name, target, false));
+        for (name, target, from_board) in board_aliases.chain(user_aliases) {
+            // stored before names were checked - such an alias could never be used
+            if is_reserved_word(name) {
+                log::warn!("skipping alias {} - it is a reserved word", name);
+                continue;
+            }
+            let id = match nom::combinator::all_consuming(pin_id_parser)(target) {
+                Ok((_, id)) => id,
+                Err(_) => {
+                    log::warn!("skipping alias {} - {} is not a pin", name, target);
+                    continue;
+                }
+            };
+            self.aliases.retain(|alias| alias.name != name);
+            let mut alias_name = String::new();
+            if alias_name.push_str(name).is_err() {
+                continue;
+            }
+            self.aliases
+                .push(Alias {
+                    name: alias_name,
+                    id,
+                    from_board,
+                })
+                .ok();
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<Id> {
+        self.aliases
+            .iter()
+            .find(|alias| alias.name == name)
+            .map(|alias| alias.id)
+    }
+}
+
+// words the parser takes as keywords - an alias with one of these names would make commands ambiguous
+const RESERVED_WORDS: [&str; 3] = ["force", "input", "as"];
+
+fn is_reserved_word(name: &str) -> bool {
+    RESERVED_WORDS.contains(&name)
+        || GPIO_VALUES.iter().any(|(word, _)| *word == name)
+        || PIN_MODES.iter().any(|(word, _)| *word == name)
+        || COMMANDS.iter().any(|syntax| syntax.name == name)
+}
+
+fn is_board_alias(board: &RushBoard, name: &str) -> bool {
+    board.aliases().any(|(board_name, _)| board_name == name)
+}
+
+#[derive(Clone, Debug)]
+pub enum AliasOperation {
+    Set { name: String<MAX_ALIAS_LEN>, id: Id },
+    Remove { name: String<MAX_ALIAS_LEN> },
+    List,
+}
+
+#[derive(Clone, Debug)]
+pub enum PwmSetting {
+    On {
+        frequency_hz: u32,
+        duty_pct: u8,
+        force: bool,
+    },
+    Off,
+}
+
+// bytes written or read by a single i2c or spi command
+const MAX_TRANSFER_BYTES: usize = 32;
+
+// bytes as hex digits separated by spaces, e.g. `01 a3 ff` - `{:#}` writes addresses like `0x3c 0x68`
+struct HexBytes<'a>(&'a [u8]);
+
+impl core::fmt::Display for HexBytes<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (index, byte) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(" ")?;
+            }
+            match f.alternate() {
+                true => write!(f, "{:#04x}", byte)?,
+                false => write!(f, "{:02x}", byte)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum I2cOperation {
+    Open {
+        sda: Id,
+        scl: Id,
+        frequency_hz: u32,
+        force: bool,
+    },
+    Close,
+    Scan,
+    // write, read and wr only differ in which half of the transfer is empty
+    Transfer {
+        address: u8,
+        write: heapless::Vec<u8, MAX_TRANSFER_BYTES>,
+        read_len: u8,
+    },
+}
+
+#[derive(Debug)]
+pub enum SpiOperation {
+    Open {
+        pins: [Id; 4], // sck, mosi, miso, cs
+        mode: SpiMode,
+        frequency_hz: u32,
+        force: bool,
+    },
+    Close,
+    Transfer {
+        bytes: heapless::Vec<u8, MAX_TRANSFER_BYTES>,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum CountAction {
+    Start { force: bool },
+    Read,
+    Reset,
+    Stop,
+}
+
+#[derive(Clone, Debug)]
+pub enum UartOperation {
+    Open {
+        tx: Id,
+        rx: Id,
+        baud_rate: u32,
+        force: bool,
+    },
+    Close,
+    Bridge,
 }
 
 #[derive(Debug)]
@@ -123,69 +1617,1063 @@ pub enum Value {
     Gpio(bool),
 }
 
-pub fn parse(input: &str) -> IResult<&str, CommandEnum> {
-    let (input, command) = nom::branch::alt((
-        read_command_parser,
-        watch_command_parser,
-        unwatch_command_parser,
-        write_command_parser,
-        /* shout_command_parser,
-        unshout_command_parser,
-        list_command_parser, */
-    ))(input)?;
+// every command with its parser and its usage - parse tries them in this order and help prints them, so the
+// help can not fall behind the parser. the arguments in the usage are explained by ArgumentHelp
+struct CommandSyntax {
+    name: &'static str, // the first word - also used to tell a mistyped command from a mistyped argument
+    usage: &'static [&'static str],
+    parser: for<'a> fn(&'a str, &RushAliases) -> IResult<&'a str, CommandEnum>,
+}
+
+static COMMANDS: [CommandSyntax; 22] = [
+    CommandSyntax {
+        name: "read",
+        usage: &["read [gpio] (as input) (force)"],
+        parser: read_command_parser,
+    },
+    CommandSyntax {
+        name: "watch",
+        usage: &["watch [gpio] (force)", "watch [touch] [threshold] (force)"],
+        parser: watch_command_parser,
+    },
+    CommandSyntax {
+        name: "unwatch",
+        usage: &["unwatch [gpio]", "unwatch [touch]"],
+        parser: unwatch_command_parser,
+    },
+    CommandSyntax {
+        name: "write",
+        usage: &["write [gpio] [value] (force)"],
+        parser: write_command_parser,
+    },
+    CommandSyntax {
+        name: "mode",
+        usage: &["mode [gpio] [mode] (force)"],
+        parser: mode_command_parser,
+    },
+    CommandSyntax {
+        name: "shout",
+        usage: &["shout [gpio] [interval] (force)"],
+        parser: shout_command_parser,
+    },
+    CommandSyntax {
+        name: "unshout",
+        usage: &["unshout [gpio]"],
+        parser: unshout_command_parser,
+    },
+    CommandSyntax {
+        name: "pwm",
+        usage: &["pwm [gpio] [frequency] [duty] (force)", "pwm [gpio] off"],
+        parser: pwm_command_parser,
+    },
+    CommandSyntax {
+        name: "i2c",
+        usage: &[
+            "i2c open [gpio] [gpio] [frequency] (force)",
+            "i2c close",
+            "i2c scan",
+            "i2c write [address] [bytes]",
+            "i2c read [address] [length]",
+            "i2c wr [address] [bytes] [length]",
+        ],
+        parser: i2c_command_parser,
+    },
+    CommandSyntax {
+        name: "spi",
+        usage: &[
+            "spi open [spi] [sck] [mosi] [miso] [cs] [spimode] [frequency] (force)",
+            "spi close [spi]",
+            "spi xfer [spi] [bytes]",
+        ],
+        parser: spi_command_parser,
+    },
+    CommandSyntax {
+        name: "uart",
+        usage: &[
+            "uart open [gpio] [gpio] [baudrate] (force)",
+            "uart close",
+            "uart bridge",
+        ],
+        parser: uart_command_parser,
+    },
+    CommandSyntax {
+        name: "count",
+        usage: &[
+            "count [gpio] start (force)",
+            "count [gpio] read",
+            "count [gpio] reset",
+            "count [gpio] stop",
+        ],
+        parser: count_command_parser,
+    },
+    CommandSyntax {
+        name: "freq",
+        usage: &["freq [gpio] [interval] (force)"],
+        parser: freq_command_parser,
+    },
+    CommandSyntax {
+        name: "touch",
+        usage: &["touch [gpio] (force)"],
+        parser: touch_command_parser,
+    },
+    CommandSyntax {
+        name: "list",
+        usage: &["list"],
+        parser: list_command_parser,
+    },
+    CommandSyntax {
+        name: "sys",
+        usage: &["sys"],
+        parser: sys_command_parser,
+    },
+    CommandSyntax {
+        name: "board",
+        usage: &["board", "board list"],
+        parser: board_command_parser,
+    },
+    CommandSyntax {
+        name: "alias",
+        usage: &["alias [name] = [gpio]", "alias list"],
+        parser: alias_command_parser,
+    },
+    CommandSyntax {
+        name: "unalias",
+        usage: &["unalias [name]"],
+        parser: unalias_command_parser,
+    },
+    CommandSyntax {
+        name: "format",
+        usage: &["format (text|json)"],
+        parser: format_command_parser,
+    },
+    CommandSyntax {
+        name: "config",
+        usage: &[
+            "config get [key]",
+            "config set [key] [text]",
+            "config reset ([key])",
+            "config list",
+        ],
+        parser: config_command_parser,
+    },
+    CommandSyntax {
+        name: "help",
+        usage: &["help", "help [command]"],
+        parser: help_command_parser,
+    },
+];
+
+// the spellings of a level - the parser only takes them as whole words, so `lol` is not read as `l`
+const GPIO_VALUES: [(&str, bool); 12] = [
+    ("high", true),
+    ("on", true),
+    ("h", true),
+    ("1", true),
+    ("true", true),
+    ("t", true),
+    ("low", false),
+    ("off", false),
+    ("l", false),
+    ("0", false),
+    ("false", false),
+    ("f", false),
+];
+
+const PIN_MODES: [(&str, RushPinMode); 6] = [
+    ("floating", RushPinMode::Floating),
+    ("pullup", RushPinMode::PullUp),
+    ("pulldown", RushPinMode::PullDown),
+    ("pushpull", RushPinMode::PushPull),
+    ("opendrain", RushPinMode::OpenDrain),
+    ("analog", RushPinMode::Analog),
+];
+
+const SPI_MODES: [(&str, SpiMode); 4] = [
+    ("mode0", SpiMode::Mode0),
+    ("mode1", SpiMode::Mode1),
+    ("mode2", SpiMode::Mode2),
+    ("mode3", SpiMode::Mode3),
+];
+
+// remembers how far the parser got and what it wanted to see there - of all alternatives the one that got
+// furthest is kept, so the error points to the argument that is wrong instead of the start of the command
+#[derive(Debug)]
+pub struct RushParseError<I> {
+    input: I,
+    expected: Expected,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Expected {
+    Context(&'static str), // set by nom::error::context, e.g. 'gpio.<n>'
+    Char(char),
+    Kind(ErrorKind),
+}
+
+impl<I: InputLength> ParseError<I> for RushParseError<I> {
+    fn from_error_kind(input: I, kind: ErrorKind) -> Self {
+        RushParseError {
+            input,
+            expected: Expected::Kind(kind),
+        }
+    }
+
+    fn from_char(input: I, c: char) -> Self {
+        RushParseError {
+            input,
+            expected: Expected::Char(c),
+        }
+    }
+
+    fn append(_input: I, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    fn or(self, other: Self) -> Self {
+        match self.input.input_len().cmp(&other.input.input_len()) {
+            core::cmp::Ordering::Less => self,
+            core::cmp::Ordering::Greater => other,
+            core::cmp::Ordering::Equal => match self.expected {
+                Expected::Context(_) => self,
+                _ => other,
+            },
+        }
+    }
+}
+
+impl<I: InputLength> ContextError<I> for RushParseError<I> {
+    // a context only names what failed right where it starts - a later failure inside keeps its own kind
+    fn add_context(input: I, context: &'static str, mut other: Self) -> Self {
+        let at_start = input.input_len() == other.input.input_len();
+        if at_start && !matches!(other.expected, Expected::Context(_)) {
+            other.expected = Expected::Context(context);
+        }
+        other
+    }
+}
+
+impl<I, E> FromExternalError<I, E> for RushParseError<I> {
+    fn from_external_error(input: I, kind: ErrorKind, _e: E) -> Self {
+        RushParseError {
+            input,
+            expected: Expected::Kind(kind),
+        }
+    }
+}
 
-    Ok((input, command))
+impl Expected {
+    fn describe(&self, at_end: bool) -> &'static str {
+        match *self {
+            Expected::Context(context) => context,
+            _ if at_end => "more",
+            Expected::Char('.') => "'.'",
+            Expected::Char('=') => "'='",
+            Expected::Char(_) => "another character",
+            Expected::Kind(ErrorKind::Space) => "a space",
+            Expected::Kind(ErrorKind::Digit) => "a number",
+            Expected::Kind(ErrorKind::Eof) => "the end of the command",
+            Expected::Kind(ErrorKind::Tag) => "a keyword",
+            Expected::Kind(_) => "a valid argument",
+        }
+    }
+}
+
+// `error 9: at col [col]: expected [what] after '[word]'`, or `error 9: unknown command '[word]'` with the command
+// that was probably meant
+pub fn format_parse_error<'b>(
+    fmt_buffer: &'b mut [u8],
+    message: &str,
+    err: nom::Err<RushParseError<&str>>,
+) -> RushResponse<'b> {
+    let command = message.split_whitespace().next().unwrap_or_default();
+    if !command.is_empty() && !COMMANDS.iter().any(|syntax| syntax.name == command) {
+        let err = RushError::ParseError {
+            position: 0,
+            expected: "a command",
+        };
+        let message = match closest_command(command) {
+            Some(suggestion) => fmt_truncate(
+                fmt_buffer,
+                format_args!(
+                    "unknown command '{}' (did you mean '{}'?)",
+                    command, suggestion
+                ),
+            ),
+            None => fmt_truncate(fmt_buffer, format_args!("unknown command '{}'", command)),
+        };
+        return RushResponse::failed(err, message);
+    }
+
+    let (position, expected) = match err {
+        nom::Err::Error(err) | nom::Err::Failure(err) => {
+            let position = message.len().saturating_sub(err.input.len());
+            (position, err.expected.describe(err.input.is_empty()))
+        }
+        nom::Err::Incomplete(_) => (message.len(), "more"),
+    };
+    let expected = if position == 0 { "a command" } else { expected };
+    let err = RushError::ParseError { position, expected };
+    // a position that is off for whatever reason must not take the board down by slicing a character apart
+    let parsed = message.get(..position).unwrap_or_default();
+    let message = match parsed.split_whitespace().last() {
+        Some(word) => fmt_truncate(fmt_buffer, format_args!("{} after '{}'", err, word)),
+        None => fmt_truncate(fmt_buffer, format_args!("{}", err)),
+    };
+    RushResponse::failed(err, message)
+}
+
+// the command with the fewest edits to get to the word, if it takes at most two
+fn closest_command(word: &str) -> Option<&'static str> {
+    COMMANDS
+        .iter()
+        .map(|syntax| (edit_distance(word, syntax.name), syntax.name))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, command)| command)
 }
 
-fn read_command_parser(input: &str) -> IResult<&str, CommandEnum> {
+// levenshtein distance - a single row is enough, words longer than any command are never close
+fn edit_distance(word: &str, command: &str) -> usize {
+    const MAX_LEN: usize = 16;
+    let (word, command) = (word.as_bytes(), command.as_bytes());
+    if word.len() > MAX_LEN || command.len() > MAX_LEN {
+        return usize::MAX;
+    }
+
+    let mut row = [0usize; MAX_LEN + 1];
+    for (index, distance) in row.iter_mut().enumerate().take(command.len() + 1) {
+        *distance = index;
+    }
+    for (word_index, word_char) in word.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = word_index + 1;
+        for (command_index, command_char) in command.iter().enumerate() {
+            let substitution = diagonal + (word_char != command_char) as usize;
+            diagonal = row[command_index + 1];
+            row[command_index + 1] = substitution
+                .min(row[command_index] + 1)
+                .min(row[command_index + 1] + 1);
+        }
+    }
+    row[command.len()]
+}
+
+// works like nom's alt over all commands - the first command that parses wins, otherwise the error that got furthest.
+// ids may be given by one of the names in `aliases`
+pub fn parse<'a>(input: &'a str, aliases: &RushAliases) -> IResult<&'a str, CommandEnum> {
+    let mut error = RushParseError::from_error_kind(input, ErrorKind::Alt);
+    for syntax in COMMANDS.iter() {
+        match (syntax.parser)(input, aliases) {
+            Err(nom::Err::Error(err)) => error = error.or(err),
+            result => return result,
+        }
+    }
+
+    Err(nom::Err::Error(error))
+}
+
+fn read_command_parser<'a>(input: &'a str, aliases: &RushAliases) -> IResult<&'a str, CommandEnum> {
     let (input, _) = nom::bytes::complete::tag("read")(input)?;
     let (input, _) = nom::character::complete::space1(input)?;
-    let (input, id) = id_parser(input)?;
+    let (input, id) = id_parser(input, aliases)?;
+    let (input, as_input) = nom::combinator::opt(nom::sequence::tuple((
+        nom::character::complete::space1,
+        nom::bytes::complete::tag("as"),
+        nom::character::complete::space1,
+        nom::bytes::complete::tag("input"),
+    )))(input)?;
+    let (input, force) = force_parser(input)?;
 
-    Ok((input, CommandEnum::Read(ReadCommand { id })))
+    Ok((
+        input,
+        CommandEnum::Read(ReadCommand {
+            id,
+            as_input: as_input.is_some(),
+            force,
+        }),
+    ))
 }
 
-fn watch_command_parser(input: &str) -> IResult<&str, CommandEnum> {
+fn watch_command_parser<'a>(
+    input: &'a str,
+    aliases: &RushAliases,
+) -> IResult<&'a str, CommandEnum> {
     let (input, _) = nom::bytes::complete::tag("watch")(input)?;
     let (input, _) = nom::character::complete::space1(input)?;
-    let (input, id) = id_parser(input)?;
+    let (input, id) = id_parser(input, aliases)?;
+    let (input, threshold) = nom::combinator::opt(nom::sequence::preceded(
+        nom::character::complete::space1,
+        nom::character::complete::u32,
+    ))(input)?;
+    let (input, force) = force_parser(input)?;
 
-    Ok((input, CommandEnum::Watch(WatchCommand { id })))
+    Ok((
+        input,
+        CommandEnum::Watch(WatchCommand {
+            id,
+            threshold,
+            force,
+        }),
+    ))
 }
 
-fn unwatch_command_parser(input: &str) -> IResult<&str, CommandEnum> {
+fn unwatch_command_parser<'a>(
+    input: &'a str,
+    aliases: &RushAliases,
+) -> IResult<&'a str, CommandEnum> {
     let (input, _) = nom::bytes::complete::tag("unwatch")(input)?;
     let (input, _) = nom::character::complete::space1(input)?;
-    let (input, id) = id_parser(input)?;
+    let (input, id) = id_parser(input, aliases)?;
 
     Ok((input, CommandEnum::Unwatch(UnwatchCommand { id })))
 }
 
-fn write_command_parser(input: &str) -> IResult<&str, CommandEnum> {
+fn write_command_parser<'a>(
+    input: &'a str,
+    aliases: &RushAliases,
+) -> IResult<&'a str, CommandEnum> {
     let (input, _) = nom::bytes::complete::tag("write")(input)?;
     let (input, _) = nom::character::complete::space1(input)?;
-    let (input, id) = id_parser(input)?;
+    let (input, id) = id_parser(input, aliases)?;
     let (input, _) = nom::character::complete::space1(input)?;
     let (input, value) = value_parser(input)?;
+    let (input, force) = force_parser(input)?;
+
+    Ok((input, CommandEnum::Write(WriteCommand { id, value, force })))
+}
+
+fn mode_command_parser<'a>(input: &'a str, aliases: &RushAliases) -> IResult<&'a str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("mode")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, id) = id_parser(input, aliases)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, mode) = pin_mode_parser(input)?;
+    let (input, force) = force_parser(input)?;
+
+    Ok((input, CommandEnum::Mode(ModeCommand { id, mode, force })))
+}
+
+fn shout_command_parser<'a>(
+    input: &'a str,
+    aliases: &RushAliases,
+) -> IResult<&'a str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("shout")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, id) = id_parser(input, aliases)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, interval) = interval_parser(input)?;
+    let (input, force) = force_parser(input)?;
+
+    Ok((
+        input,
+        CommandEnum::Shout(ShoutCommand {
+            id,
+            interval,
+            force,
+        }),
+    ))
+}
+
+fn unshout_command_parser<'a>(
+    input: &'a str,
+    aliases: &RushAliases,
+) -> IResult<&'a str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("unshout")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, id) = id_parser(input, aliases)?;
+
+    Ok((input, CommandEnum::Unshout(UnshoutCommand { id })))
+}
+
+fn pwm_command_parser<'a>(input: &'a str, aliases: &RushAliases) -> IResult<&'a str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("pwm")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, id) = id_parser(input, aliases)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, setting) = nom::error::context(
+        "'off' or a frequency",
+        nom::branch::alt((
+            nom::combinator::value(PwmSetting::Off, nom::bytes::complete::tag("off")),
+            pwm_on_parser,
+        )),
+    )(input)?;
+
+    Ok((input, CommandEnum::Pwm(PwmCommand { id, setting })))
+}
+
+fn i2c_command_parser<'a>(input: &'a str, aliases: &RushAliases) -> IResult<&'a str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("i2c")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, operation) = nom::error::context(
+        "open, close, scan, write, read or wr",
+        nom::branch::alt((
+            |input| i2c_open_parser(input, aliases),
+            nom::combinator::value(I2cOperation::Close, nom::bytes::complete::tag("close")),
+            nom::combinator::value(I2cOperation::Scan, nom::bytes::complete::tag("scan")),
+            i2c_write_read_parser,
+            i2c_write_parser,
+            i2c_read_parser,
+        )),
+    )(input)?;
+
+    Ok((input, CommandEnum::I2c(I2cCommand { operation })))
+}
+
+fn i2c_open_parser<'a>(input: &'a str, aliases: &RushAliases) -> IResult<&'a str, I2cOperation> {
+    let (input, _) = nom::bytes::complete::tag("open")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, sda) = id_parser(input, aliases)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, scl) = id_parser(input, aliases)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, frequency_hz) = frequency_parser(input)?;
+    let (input, force) = force_parser(input)?;
+
+    Ok((
+        input,
+        I2cOperation::Open {
+            sda,
+            scl,
+            frequency_hz,
+            force,
+        },
+    ))
+}
+
+fn i2c_write_parser(input: &str) -> IResult<&str, I2cOperation> {
+    let (input, _) = nom::bytes::complete::tag("write")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, address) = i2c_address_parser(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, write) = hex_bytes_parser(input)?;
+
+    Ok((
+        input,
+        I2cOperation::Transfer {
+            address,
+            write,
+            read_len: 0,
+        },
+    ))
+}
+
+fn i2c_read_parser(input: &str) -> IResult<&str, I2cOperation> {
+    let (input, _) = nom::bytes::complete::tag("read")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, address) = i2c_address_parser(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, read_len) = i2c_length_parser(input)?;
+
+    Ok((
+        input,
+        I2cOperation::Transfer {
+            address,
+            write: heapless::Vec::new(),
+            read_len,
+        },
+    ))
+}
+
+fn i2c_write_read_parser(input: &str) -> IResult<&str, I2cOperation> {
+    let (input, _) = nom::bytes::complete::tag("wr")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, address) = i2c_address_parser(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, write) = hex_bytes_parser(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, read_len) = i2c_length_parser(input)?;
+
+    Ok((
+        input,
+        I2cOperation::Transfer {
+            address,
+            write,
+            read_len,
+        },
+    ))
+}
+
+fn i2c_address_parser(input: &str) -> IResult<&str, u8> {
+    nom::error::context(
+        "an address like 0x3c",
+        nom::branch::alt((
+            nom::combinator::map_res(
+                nom::sequence::preceded(
+                    nom::bytes::complete::tag("0x"),
+                    nom::character::complete::hex_digit1,
+                ),
+                |digits: &str| u8::from_str_radix(digits, 16),
+            ),
+            nom::character::complete::u8,
+        )),
+    )(input)
+}
+
+fn i2c_length_parser(input: &str) -> IResult<&str, u8> {
+    nom::error::context(
+        "a length from 1 to 32",
+        nom::combinator::verify(nom::character::complete::u8, |len| {
+            (1..=MAX_TRANSFER_BYTES as u8).contains(len)
+        }),
+    )(input)
+}
+
+// an even number of hex digits - at most MAX_TRANSFER_BYTES bytes
+fn hex_bytes_parser(input: &str) -> IResult<&str, heapless::Vec<u8, MAX_TRANSFER_BYTES>> {
+    let hex_bytes =
+        nom::combinator::map_opt(nom::character::complete::hex_digit1, |digits: &str| {
+            if digits.len() % 2 != 0 {
+                return None;
+            }
+            let mut bytes = heapless::Vec::new();
+            for pair in digits.as_bytes().chunks(2) {
+                let pair = from_utf8(pair).ok()?;
+                bytes.push(u8::from_str_radix(pair, 16).ok()?).ok()?;
+            }
+            Some(bytes)
+        });
+    nom::error::context("hex bytes like 01a3ff", hex_bytes)(input)
+}
+
+fn spi_command_parser<'a>(input: &'a str, aliases: &RushAliases) -> IResult<&'a str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("spi")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, command) = nom::error::context(
+        "open, close or xfer",
+        nom::branch::alt((
+            |input| spi_open_parser(input, aliases),
+            |input| spi_close_parser(input, aliases),
+            |input| spi_xfer_parser(input, aliases),
+        )),
+    )(input)?;
+
+    Ok((input, CommandEnum::Spi(command)))
+}
+
+fn spi_open_parser<'a>(input: &'a str, aliases: &RushAliases) -> IResult<&'a str, SpiCommand> {
+    let (input, _) = nom::bytes::complete::tag("open")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, bus) = id_parser(input, aliases)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, sck) = id_parser(input, aliases)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, mosi) = id_parser(input, aliases)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, miso) = id_parser(input, aliases)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, cs) = id_parser(input, aliases)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, mode) = spi_mode_parser(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, frequency_hz) = frequency_parser(input)?;
+    let (input, force) = force_parser(input)?;
+
+    Ok((
+        input,
+        SpiCommand {
+            bus,
+            operation: SpiOperation::Open {
+                pins: [sck, mosi, miso, cs],
+                mode,
+                frequency_hz,
+                force,
+            },
+        },
+    ))
+}
+
+fn spi_close_parser<'a>(input: &'a str, aliases: &RushAliases) -> IResult<&'a str, SpiCommand> {
+    let (input, _) = nom::bytes::complete::tag("close")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, bus) = id_parser(input, aliases)?;
+
+    Ok((
+        input,
+        SpiCommand {
+            bus,
+            operation: SpiOperation::Close,
+        },
+    ))
+}
+
+fn spi_xfer_parser<'a>(input: &'a str, aliases: &RushAliases) -> IResult<&'a str, SpiCommand> {
+    let (input, _) = nom::bytes::complete::tag("xfer")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, bus) = id_parser(input, aliases)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, bytes) = hex_bytes_parser(input)?;
+
+    Ok((
+        input,
+        SpiCommand {
+            bus,
+            operation: SpiOperation::Transfer { bytes },
+        },
+    ))
+}
+
+fn count_command_parser<'a>(
+    input: &'a str,
+    aliases: &RushAliases,
+) -> IResult<&'a str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("count")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, id) = id_parser(input, aliases)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, action) = nom::error::context(
+        "start, read, reset or stop",
+        nom::branch::alt((
+            nom::combinator::map(
+                nom::sequence::preceded(nom::bytes::complete::tag("start"), force_parser),
+                |force| CountAction::Start { force },
+            ),
+            nom::combinator::value(CountAction::Read, nom::bytes::complete::tag("read")),
+            nom::combinator::value(CountAction::Reset, nom::bytes::complete::tag("reset")),
+            nom::combinator::value(CountAction::Stop, nom::bytes::complete::tag("stop")),
+        )),
+    )(input)?;
+
+    Ok((input, CommandEnum::Count(CountCommand { id, action })))
+}
+
+fn freq_command_parser<'a>(input: &'a str, aliases: &RushAliases) -> IResult<&'a str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("freq")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, id) = id_parser(input, aliases)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, gate) = interval_parser(input)?;
+    let (input, force) = force_parser(input)?;
+
+    Ok((input, CommandEnum::Freq(FreqCommand { id, gate, force })))
+}
+
+fn touch_command_parser<'a>(
+    input: &'a str,
+    aliases: &RushAliases,
+) -> IResult<&'a str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("touch")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, id) = id_parser(input, aliases)?;
+
+    let (input, force) = force_parser(input)?;
+
+    Ok((input, CommandEnum::Touch(TouchCommand { id, force })))
+}
+
+fn uart_command_parser<'a>(input: &'a str, aliases: &RushAliases) -> IResult<&'a str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("uart")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, operation) = nom::error::context(
+        "open, close or bridge",
+        nom::branch::alt((
+            |input| uart_open_parser(input, aliases),
+            nom::combinator::value(UartOperation::Close, nom::bytes::complete::tag("close")),
+            nom::combinator::value(UartOperation::Bridge, nom::bytes::complete::tag("bridge")),
+        )),
+    )(input)?;
 
-    Ok((input, CommandEnum::Write(WriteCommand { id, value })))
+    Ok((input, CommandEnum::Uart(UartCommand { operation })))
 }
 
-fn id_parser(input: &str) -> IResult<&str, Id> {
-    let (input, id) = gpio_id_parser(input)?;
+fn uart_open_parser<'a>(input: &'a str, aliases: &RushAliases) -> IResult<&'a str, UartOperation> {
+    let (input, _) = nom::bytes::complete::tag("open")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, tx) = id_parser(input, aliases)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, rx) = id_parser(input, aliases)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, baud_rate) =
+        nom::error::context("a baudrate like 115200", nom::character::complete::u32)(input)?;
+    let (input, force) = force_parser(input)?;
+
+    Ok((
+        input,
+        UartOperation::Open {
+            tx,
+            rx,
+            baud_rate,
+            force,
+        },
+    ))
+}
+
+fn list_command_parser<'a>(
+    input: &'a str,
+    _aliases: &RushAliases,
+) -> IResult<&'a str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("list")(input)?;
+
+    Ok((input, CommandEnum::List(ListCommand {})))
+}
+
+fn sys_command_parser<'a>(input: &'a str, _aliases: &RushAliases) -> IResult<&'a str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("sys")(input)?;
+
+    Ok((input, CommandEnum::Sys(SysCommand {})))
+}
+
+fn board_command_parser<'a>(
+    input: &'a str,
+    _aliases: &RushAliases,
+) -> IResult<&'a str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("board")(input)?;
+    let (input, list) = nom::combinator::opt(nom::sequence::preceded(
+        nom::character::complete::space1,
+        nom::bytes::complete::tag("list"),
+    ))(input)?;
+
+    Ok((
+        input,
+        CommandEnum::Board(BoardCommand {
+            list: list.is_some(),
+        }),
+    ))
+}
+
+fn format_command_parser<'a>(
+    input: &'a str,
+    _aliases: &RushAliases,
+) -> IResult<&'a str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("format")(input)?;
+    let (input, format) = nom::combinator::opt(nom::sequence::preceded(
+        nom::character::complete::space1,
+        nom::branch::alt((
+            nom::combinator::value(RushFormat::Text, nom::bytes::complete::tag("text")),
+            nom::combinator::value(RushFormat::Json, nom::bytes::complete::tag("json")),
+        )),
+    ))(input)?;
+
+    Ok((input, CommandEnum::Format(FormatCommand { format })))
+}
+
+fn alias_command_parser<'a>(
+    input: &'a str,
+    _aliases: &RushAliases,
+) -> IResult<&'a str, CommandEnum> {
+    let (input, operation) = nom::branch::alt((
+        alias_set_parser,
+        nom::combinator::value(
+            AliasOperation::List,
+            nom::bytes::complete::tag("alias list"),
+        ),
+    ))(input)?;
+
+    Ok((input, CommandEnum::Alias(AliasCommand { operation })))
+}
+
+fn unalias_command_parser<'a>(
+    input: &'a str,
+    _aliases: &RushAliases,
+) -> IResult<&'a str, CommandEnum> {
+    let (input, operation) = alias_remove_parser(input)?;
+
+    Ok((input, CommandEnum::Alias(AliasCommand { operation })))
+}
+
+fn alias_set_parser(input: &str) -> IResult<&str, AliasOperation> {
+    let (input, _) = nom::bytes::complete::tag("alias")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, name) = nom::error::context("an alias name", alias_name_parser)(input)?;
+    let (input, _) = nom::character::complete::space0(input)?;
+    let (input, _) = nom::character::complete::char('=')(input)?;
+    let (input, _) = nom::character::complete::space0(input)?;
+    let (input, id) = pin_id_parser(input)?;
+
+    Ok((input, AliasOperation::Set { name, id }))
+}
+
+fn alias_remove_parser(input: &str) -> IResult<&str, AliasOperation> {
+    let (input, _) = nom::bytes::complete::tag("unalias")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, name) = nom::error::context("an alias name", alias_name_parser)(input)?;
+
+    Ok((input, AliasOperation::Remove { name }))
+}
+
+fn config_command_parser<'a>(
+    input: &'a str,
+    _aliases: &RushAliases,
+) -> IResult<&'a str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("config")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    nom::error::context(
+        "get, set, reset or list",
+        nom::branch::alt((
+            config_get_command_parser,
+            config_set_command_parser,
+            config_reset_command_parser,
+            config_list_command_parser,
+        )),
+    )(input)
+}
+
+fn config_get_command_parser(input: &str) -> IResult<&str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("get")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, key) = config_key_parser(input)?;
+
+    Ok((input, CommandEnum::ConfigGet(ConfigGetCommand { key })))
+}
+
+fn config_set_command_parser(input: &str) -> IResult<&str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("set")(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, key) = config_key_parser(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, value) = nom::combinator::map_opt(nom::combinator::rest, |value: &str| {
+        let mut string = String::new();
+        string.push_str(value).ok().map(|()| string)
+    })(input)?;
+
+    Ok((
+        input,
+        CommandEnum::ConfigSet(ConfigSetCommand { key, value }),
+    ))
+}
+
+fn config_reset_command_parser(input: &str) -> IResult<&str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("reset")(input)?;
+    let (input, key) = nom::combinator::opt(nom::sequence::preceded(
+        nom::character::complete::space1,
+        config_key_parser,
+    ))(input)?;
+    // a mistyped key must not reset everything
+    let (input, _) = nom::character::complete::multispace0(input)?;
+    let (input, _) = nom::combinator::eof(input)?;
+
+    Ok((input, CommandEnum::ConfigReset(ConfigResetCommand { key })))
+}
+
+fn config_list_command_parser(input: &str) -> IResult<&str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("list")(input)?;
+
+    Ok((input, CommandEnum::ConfigList(ConfigListCommand {})))
+}
+
+fn help_command_parser<'a>(
+    input: &'a str,
+    _aliases: &RushAliases,
+) -> IResult<&'a str, CommandEnum> {
+    let (input, _) = nom::bytes::complete::tag("help")(input)?;
+    let (input, command) = nom::branch::alt((
+        nom::combinator::map(
+            nom::sequence::preceded(
+                nom::character::complete::space1,
+                nom::error::context("a command", command_name_parser),
+            ),
+            Some,
+        ),
+        nom::combinator::value(
+            None,
+            nom::sequence::preceded(nom::character::complete::multispace0, nom::combinator::eof),
+        ),
+    ))(input)?;
+
+    Ok((input, CommandEnum::Help(HelpCommand { command })))
+}
+
+fn command_name_parser(input: &str) -> IResult<&str, &'static str> {
+    let (rest, name) = nom::character::complete::alphanumeric1(input)?;
+    match COMMANDS.iter().find(|syntax| syntax.name == name) {
+        Some(syntax) => Ok((rest, syntax.name)),
+        None => Err(nom::Err::Error(RushParseError::from_error_kind(
+            input,
+            ErrorKind::Tag,
+        ))),
+    }
+}
+
+fn id_parser<'a>(input: &'a str, aliases: &RushAliases) -> IResult<&'a str, Id> {
+    let (input, id) = nom::error::context(
+        "'gpio.<n>'",
+        nom::branch::alt((pin_id_parser, |input| alias_id_parser(input, aliases))),
+    )(input)?;
+
+    Ok((input, id))
+}
+
+// an id as it is written out - aliases can only point to these, not to other aliases
+fn pin_id_parser(input: &str) -> IResult<&str, Id> {
+    let (input, id) = nom::branch::alt((
+        gpio_id_parser,
+        adc_id_parser,
+        spi_id_parser,
+        touch_id_parser,
+    ))(input)?;
 
     Ok((input, id))
 }
 
+fn alias_id_parser<'a>(input: &'a str, aliases: &RushAliases) -> IResult<&'a str, Id> {
+    let (rest, name) = alias_name_parser(input)?;
+    match aliases.find(&name) {
+        Some(id) => Ok((rest, id)),
+        None => Err(nom::Err::Error(RushParseError::from_error_kind(
+            input,
+            ErrorKind::Tag,
+        ))),
+    }
+}
+
+fn alias_name_parser(input: &str) -> IResult<&str, String<MAX_ALIAS_LEN>> {
+    let (rest, name) = nom::combinator::recognize(nom::sequence::pair(
+        nom::character::complete::satisfy(|c| c.is_ascii_alphabetic()),
+        nom::bytes::complete::take_while(|c: char| {
+            c.is_ascii_alphanumeric() || c == '_' || c == '-'
+        }),
+    ))(input)?;
+    let mut alias_name = String::new();
+    match alias_name.push_str(name) {
+        Ok(()) => Ok((rest, alias_name)),
+        // reported at the start of the name - errors have to point into the remaining input
+        Err(()) => Err(nom::Err::Error(RushParseError::from_error_kind(
+            input,
+            ErrorKind::TooLarge,
+        ))),
+    }
+}
+
 fn gpio_id_parser(input: &str) -> IResult<&str, Id> {
     let (input, _) = nom::bytes::complete::tag("gpio")(input)?;
     let (input, _) = nom::character::complete::char('.')(input)?;
-    let (input, id) = nom::character::complete::digit1(input)?;
-    let id = id.parse::<u8>().unwrap();
+    let (input, id) = nom::character::complete::u8(input)?;
 
     Ok((input, Id::Gpio(id)))
 }
 
+fn adc_id_parser(input: &str) -> IResult<&str, Id> {
+    let (input, _) = nom::bytes::complete::tag("adc")(input)?;
+    let (input, _) = nom::character::complete::char('.')(input)?;
+    let (input, id) = nom::character::complete::u8(input)?;
+
+    Ok((input, Id::Adc(id)))
+}
+
+fn spi_id_parser(input: &str) -> IResult<&str, Id> {
+    let (input, _) = nom::bytes::complete::tag("spi")(input)?;
+    let (input, _) = nom::character::complete::char('.')(input)?;
+    let (input, id) = nom::character::complete::u8(input)?;
+
+    Ok((input, Id::Spi(id)))
+}
+
+fn touch_id_parser(input: &str) -> IResult<&str, Id> {
+    let (input, _) = nom::bytes::complete::tag("touch")(input)?;
+    let (input, _) = nom::character::complete::char('.')(input)?;
+    let (input, id) = nom::character::complete::u8(input)?;
+
+    Ok((input, Id::Touch(id)))
+}
+
+fn force_parser(input: &str) -> IResult<&str, bool> {
+    let (input, force) = nom::combinator::opt(nom::sequence::preceded(
+        nom::character::complete::space1,
+        nom::bytes::complete::tag("force"),
+    ))(input)?;
+
+    Ok((input, force.is_some()))
+}
+
 fn value_parser(input: &str) -> IResult<&str, Value> {
     let (input, value) = gpio_value_parser(input)?;
 
@@ -193,36 +2681,121 @@ fn value_parser(input: &str) -> IResult<&str, Value> {
 }
 
 fn gpio_value_parser(input: &str) -> IResult<&str, Value> {
-    let (input, value) =
-        nom::branch::alt((gpio_value_true_parser, gpio_value_false_parser))(input)?;
+    let (input, level) = keyword_parser(input, &GPIO_VALUES, "'high' or 'low'")?;
 
-    Ok((input, value))
+    Ok((input, Value::Gpio(level)))
 }
 
-fn gpio_value_true_parser(input: &str) -> IResult<&str, Value> {
-    //Allow high, on, h, 1, true, t
-    let (input, _) = nom::branch::alt((
-        nom::bytes::complete::tag("high"),
-        nom::bytes::complete::tag("on"),
-        nom::bytes::complete::tag("h"),
-        nom::bytes::complete::tag("1"),
-        nom::bytes::complete::tag("true"),
-        nom::bytes::complete::tag("t"),
-    ))(input)?;
+fn pin_mode_parser(input: &str) -> IResult<&str, RushPinMode> {
+    keyword_parser(input, &PIN_MODES, "a pin mode")
+}
 
-    Ok(()).map(|_| (input, Value::Gpio(true)))
+fn config_key_parser(input: &str) -> IResult<&str, RushConfigKey> {
+    match RushConfigKey::ALL
+        .iter()
+        .find(|key| starts_with_word(input, key.name()))
+    {
+        Some(key) => Ok((&input[key.name().len()..], *key)),
+        None => {
+            let err = RushParseError::from_error_kind(input, ErrorKind::Tag);
+            Err(nom::Err::Error(RushParseError::add_context(
+                input,
+                "a config key",
+                err,
+            )))
+        }
+    }
 }
 
-fn gpio_value_false_parser(input: &str) -> IResult<&str, Value> {
-    //allow low, off, l, 0, false, f
-    let (input, _) = nom::branch::alt((
-        nom::bytes::complete::tag("low"),
-        nom::bytes::complete::tag("off"),
-        nom::bytes::complete::tag("l"),
-        nom::bytes::complete::tag("0"),
-        nom::bytes::complete::tag("false"),
-        nom::bytes::complete::tag("f"),
-    ))(input)?;
+fn spi_mode_parser(input: &str) -> IResult<&str, SpiMode> {
+    keyword_parser(input, &SPI_MODES, "'mode0' to 'mode3'")
+}
+
+// the first keyword of the table the input starts with as a whole word - `expected` names them in the error
+fn keyword_parser<'a, T: Clone>(
+    input: &'a str,
+    keywords: &[(&'static str, T)],
+    expected: &'static str,
+) -> IResult<&'a str, T> {
+    match keywords
+        .iter()
+        .find(|(keyword, _)| starts_with_word(input, keyword))
+    {
+        Some((keyword, value)) => Ok((&input[keyword.len()..], value.clone())),
+        None => {
+            let err = RushParseError::from_error_kind(input, ErrorKind::Tag);
+            Err(nom::Err::Error(RushParseError::add_context(
+                input, expected, err,
+            )))
+        }
+    }
+}
+
+// `word` followed by the end of the input or whitespace - so `low` does not match `lol` and `t` not `toggle`
+fn starts_with_word(input: &str, word: &str) -> bool {
+    match input.strip_prefix(word) {
+        None => false,
+        Some(rest) => rest.is_empty() || rest.starts_with(char::is_whitespace),
+    }
+}
+
+fn spi_mode_name(mode: &SpiMode) -> &'static str {
+    match mode {
+        SpiMode::Mode0 => "mode0",
+        SpiMode::Mode1 => "mode1",
+        SpiMode::Mode2 => "mode2",
+        SpiMode::Mode3 => "mode3",
+    }
+}
+
+fn pwm_on_parser(input: &str) -> IResult<&str, PwmSetting> {
+    let (input, frequency_hz) = frequency_parser(input)?;
+    let (input, _) = nom::character::complete::space1(input)?;
+    let (input, duty_pct) =
+        nom::error::context("a duty from 0 to 100", nom::character::complete::u8)(input)?;
+    let (input, _) = nom::combinator::opt(nom::character::complete::char('%'))(input)?;
+    let (input, force) = force_parser(input)?;
+
+    Ok((
+        input,
+        PwmSetting::On {
+            frequency_hz,
+            duty_pct,
+            force,
+        },
+    ))
+}
+
+fn frequency_parser(input: &str) -> IResult<&str, u32> {
+    let (input, value) =
+        nom::error::context("a frequency like 1khz", nom::character::complete::u32)(input)?;
+    let (input, unit) = nom::combinator::opt(nom::branch::alt((
+        nom::bytes::complete::tag("mhz"),
+        nom::bytes::complete::tag("khz"),
+        nom::bytes::complete::tag("hz"),
+    )))(input)?;
+
+    let frequency_hz = match unit {
+        Some("mhz") => value.saturating_mul(1_000_000),
+        Some("khz") => value.saturating_mul(1000),
+        _ => value,
+    };
+
+    Ok((input, frequency_hz))
+}
+
+fn interval_parser(input: &str) -> IResult<&str, Duration> {
+    let (input, value) =
+        nom::error::context("an interval like 100ms", nom::character::complete::u32)(input)?;
+    let (input, unit) = nom::combinator::opt(nom::branch::alt((
+        nom::bytes::complete::tag("ms"),
+        nom::bytes::complete::tag("s"),
+    )))(input)?;
+
+    let interval = match unit {
+        Some("s") => Duration::from_secs(value as u64),
+        _ => Duration::from_millis(value as u64),
+    };
 
-    Ok(()).map(|_| (input, Value::Gpio(false)))
+    Ok((input, interval))
 }
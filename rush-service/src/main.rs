@@ -6,31 +6,56 @@
 #![feature(error_in_core)]
 
 mod command_parser;
+mod rush_adc_calibration;
+mod rush_board;
+mod rush_config;
+mod rush_error;
 mod rush_pin_manager;
+mod rush_response;
+mod rush_routed_pin;
+mod rush_session;
+mod rush_system_info;
+mod rush_touch_sensor;
 mod rush_wifi;
 
 use crate::command_parser::parse;
 use crate::command_parser::Command;
-use crate::rush_pin_manager::RushPinManager;
+use crate::command_parser::{RushAliases, RushAliasesMutex};
+use crate::rush_config::{RushConfig, RushConfigKey, RushConfigMutex};
+use crate::rush_error::{format_error, format_stopped, RushError};
+use crate::rush_pin_manager::{
+    lock_pin_manager, RushPinManager, RushPinManagerMutex, MAX_CLIENTS, PIN_EVENTS,
+};
+use crate::rush_response::{format_response, RushResponse};
+use crate::rush_session::RushSession;
 
 use core::str::from_utf8;
 use embassy_executor::_export::StaticCell;
 use embassy_executor::{Executor, SpawnError};
-use embassy_futures::select::{select, Either};
+use embassy_futures::select::{select4, Either4};
 use embassy_net::tcp::TcpSocket;
-use embassy_net::{IpListenEndpoint, Stack};
+use embassy_net::{IpListenEndpoint, Ipv4Address, Stack};
+use embassy_sync::mutex::Mutex;
+use embassy_sync::pubsub::WaitResult;
 use embassy_time::{Duration, Timer};
 use embedded_io::asynch::Write;
-use embedded_svc::wifi::{AccessPointConfiguration, Configuration};
-use esp32s3_hal::clock::{ClockControl, CpuClock};
+use embedded_svc::wifi::{
+    AccessPointConfiguration, AuthMethod, ClientConfiguration, Configuration,
+};
+use esp32s3_hal::clock::{ClockControl, Clocks, CpuClock};
+use esp32s3_hal::peripherals::{Interrupt, Peripherals};
 use esp32s3_hal::prelude::*;
-use esp32s3_hal::{embassy, peripherals::Peripherals, timer::TimerGroup, Rtc};
+use esp32s3_hal::{embassy, interrupt, timer::TimerGroup, Rtc};
 use esp32s3_hal::{Rng, IO};
 use esp_backtrace as _;
 use esp_println::logger::init_logger;
 use esp_wifi::wifi::WifiDevice;
 
 static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+static CLOCKS: StaticCell<Clocks> = StaticCell::new();
+static PIN_MANAGER: StaticCell<RushPinManagerMutex> = StaticCell::new();
+static CONFIG: StaticCell<RushConfigMutex> = StaticCell::new();
+static ALIASES: StaticCell<RushAliasesMutex> = StaticCell::new();
 
 #[entry]
 fn main() -> ! {
@@ -40,52 +65,124 @@ fn main() -> ! {
     let peripherals = Peripherals::take();
 
     let system = peripherals.SYSTEM.split();
-    let clocks = ClockControl::configure(system.clock_control, CpuClock::Clock240MHz).freeze();
+    let clocks = &*CLOCKS
+        .init(ClockControl::configure(system.clock_control, CpuClock::Clock240MHz).freeze());
+    rush_system_info::init(clocks);
 
     // disable watchdog timers
     let mut rtc = Rtc::new(peripherals.RTC_CNTL);
     rtc.swd.disable();
     rtc.rwdt.disable();
 
-    // setup pins
+    // load persisted settings - network name, password, address, port and board live there
+    let mut rng = Rng::new(peripherals.RNG);
+    let config = RushConfig::load(&mut rng);
+
+    // setup pins - the gpio interrupt wakes up the tasks waiting for edges on watched pins
     let io = IO::new(peripherals.GPIO, peripherals.IO_MUX);
-    let pin_manager = rush_pin_manager::RushPinManager::new(io.pins);
+    let analog = peripherals.SENS.split();
+    let pin_manager = rush_pin_manager::RushPinManager::new(
+        io.pins,
+        analog.adc1,
+        peripherals.LEDC,
+        peripherals.I2C0,
+        peripherals.SPI2,
+        peripherals.SPI3,
+        peripherals.UART1,
+        peripherals.PCNT,
+        system.peripheral_clock_control,
+        clocks,
+        config.board(),
+    );
+    let aliases = RushAliases::new(config.board(), &config);
+    if let Err(e) = interrupt::enable(Interrupt::GPIO, interrupt::Priority::Priority1) {
+        panic!("could not enable gpio interrupt: {:?}", e);
+    }
+    if let Err(e) = interrupt::enable(Interrupt::PCNT, interrupt::Priority::Priority1) {
+        panic!("could not enable pcnt interrupt: {:?}", e);
+    }
 
-    // initialize wifi
+    // initialize wifi - join the network set with `config set sta_ssid`, or open our own if there is none
+    let mut sta_ssid = heapless::String::<32>::new();
+    let mut sta_password = heapless::String::<64>::new();
+    let station = match config.sta_ssid() {
+        // both were checked when they were set - a value that does not fit anyway falls back to our own network
+        Some(ssid) => {
+            sta_ssid.push_str(ssid).is_ok() && sta_password.push_str(config.sta_password()).is_ok()
+        }
+        None => false,
+    };
+    let wifi_configuration = match (station, rush_wifi::take_access_point_fallback()) {
+        (true, false) => Configuration::Client(ClientConfiguration {
+            ssid: sta_ssid,
+            password: sta_password,
+            ..Default::default()
+        }),
+        _ => {
+            // the default password differs per board - the serial console is the only place that shows it
+            if config.is_default(RushConfigKey::Password) {
+                log::warn!(
+                    "access point {} uses the default password of this board: {} - change it with `config set password`",
+                    config.ssid(),
+                    config.password()
+                );
+            }
+            Configuration::AccessPoint(AccessPointConfiguration {
+                ssid: config.ssid().into(),
+                password: config.password().into(),
+                auth_method: AuthMethod::WPA2Personal,
+                ..Default::default()
+            })
+        }
+    };
     let rush_wifi = rush_wifi::RushWifi::new(
-        TimerGroup::new(peripherals.TIMG1, &clocks).timer0,
-        Rng::new(peripherals.RNG),
+        TimerGroup::new(peripherals.TIMG1, clocks).timer0,
+        rng,
         system.radio_clock_control,
-        &clocks,
+        clocks,
         peripherals.RADIO.split().0,
-        &Configuration::AccessPoint(AccessPointConfiguration {
-            ssid: "rush".into(),
-            ..Default::default()
-        }),
+        &wifi_configuration,
+        Ipv4Address(config.address()),
+        Duration::from_millis(config.sta_retry_ms() as u64),
     );
 
     // setup embassy
-    let embassy_timer = TimerGroup::new(peripherals.TIMG0, &clocks).timer0;
-    embassy::init(&clocks, embassy_timer);
+    let embassy_timer = TimerGroup::new(peripherals.TIMG0, clocks).timer0;
+    embassy::init(clocks, embassy_timer);
 
     log::info!("setup done - starting embassy executor...");
 
     let executor = EXECUTOR.init(Executor::new());
     executor.run(|spawner| {
         let wifi_stack = rush_wifi.start(&spawner);
-        if let Err(SpawnError::Busy) = spawner.spawn(main_loop(wifi_stack, pin_manager)) {
-            panic!("could not spawn embassy task: main_loop - seems like it is already running? this should not be possible...");
+        let pin_manager = &*PIN_MANAGER.init(Mutex::new(pin_manager));
+        let config = &*CONFIG.init(Mutex::new(config));
+        let aliases = &*ALIASES.init(Mutex::new(aliases));
+        if let Err(SpawnError::Busy) = spawner.spawn(rush_pin_manager::watch_pins(pin_manager)) {
+            panic!("could not spawn embassy task: watch_pins - seems like it is already running? this should not be possible...");
+        }
+        for client_id in 0..MAX_CLIENTS as u8 {
+            if let Err(SpawnError::Busy) = spawner.spawn(client_loop(wifi_stack, pin_manager, config, aliases, client_id)) {
+                panic!("could not spawn embassy task: client_loop - the task pool is smaller than MAX_CLIENTS");
+            }
         }
     });
 }
 
-#[embassy_executor::task]
-async fn main_loop(stack: &'static Stack<WifiDevice<'static>>, mut pin_manager: RushPinManager) {
+// one task per client - pool_size has to match rush_pin_manager::MAX_CLIENTS
+#[embassy_executor::task(pool_size = 4)]
+async fn client_loop(
+    stack: &'static Stack<WifiDevice<'static>>,
+    pin_manager: &'static RushPinManagerMutex,
+    config: &'static RushConfigMutex,
+    aliases: &'static RushAliasesMutex,
+    client_id: u8,
+) {
     let mut rx_buffer = [0; 4096];
     let mut tx_buffer = [0; 4096];
 
     loop {
-        if stack.is_link_up() {
+        if stack.is_link_up() && stack.is_config_up() {
             break;
         }
         Timer::after(Duration::from_millis(500)).await;
@@ -95,47 +192,67 @@ async fn main_loop(stack: &'static Stack<WifiDevice<'static>>, mut pin_manager:
     socket.set_timeout(Some(embassy_net::SmolDuration::from_secs(10)));
     socket.set_keep_alive(Some(embassy_net::SmolDuration::from_secs(3)));
 
+    let port = config.lock().await.port();
+    let mut pin_events = match PIN_EVENTS.subscriber() {
+        Ok(subscriber) => subscriber,
+        Err(_) => {
+            panic!("could not subscribe to pin events - are there more clients than MAX_CLIENTS?")
+        }
+    };
+
     loop {
-        log::info!("waiting for connection...");
-        if let Err(e) = socket
-            .accept(IpListenEndpoint {
-                addr: None,
-                port: 2000,
-            })
-            .await
-        {
-            log::error!("socket.accept() failed: {:?}", e);
+        log::info!("client {}: waiting for connection...", client_id);
+        if let Err(e) = socket.accept(IpListenEndpoint { addr: None, port }).await {
+            log::error!("client {}: socket.accept() failed: {:?}", client_id, e);
             panic!();
         }
-        log::info!("connected!");
+        log::info!("client {}: connected!", client_id);
 
-        let mut pin_manager_fmt_buffer = [0u8; 1024];
+        // events published while no one was connected are of no interest to the new client
+        while pin_events.try_next_message().is_some() {}
+        let mut session = RushSession::new(client_id);
+
+        let mut fmt_buffer = [0u8; 2048]; // json takes about three times the room of text
 
         let mut read_buffer = [0u8; 1024];
         let mut read_pos = 0;
         loop {
-            let select_result = select(
-                pin_manager.poll_watched_pins(&mut pin_manager_fmt_buffer),
+            let select_result = select4(
+                pin_events.next_message(),
+                Timer::at(session.next_timer()),
                 socket.read(&mut read_buffer[read_pos..]),
+                Timer::at(session.next_uart_poll()),
             )
             .await;
-            match select_result {
+            let msg = match select_result {
                 // messages from watched pins
-                Either::First(msg) => {
-                    if socket.write_all(msg.as_bytes()).await
-                        == Err(embassy_net::tcp::Error::ConnectionReset)
-                    {
-                        log::error!("could not send message to client - connection reset");
-                        break;
+                Either4::First(WaitResult::Message(event)) => {
+                    match session.format_pin_event(event, &mut fmt_buffer) {
+                        None => continue, // watched by another client
+                        Some(msg) => msg,
                     }
-                    if socket.flush().await == Err(embassy_net::tcp::Error::ConnectionReset) {
-                        log::error!("could not send message to client - connection reset");
-                        break;
+                }
+                Either4::First(WaitResult::Lagged(count)) => {
+                    session.format_lagged(count, &mut fmt_buffer)
+                }
+
+                // messages from shouted pins and finished frequency measurements
+                Either4::Second(()) => {
+                    let mut pin_manager = lock_pin_manager(pin_manager).await;
+                    session.poll_timers(&mut pin_manager, &mut fmt_buffer)
+                }
+
+                // bytes from the bridged uart
+                Either4::Fourth(()) => {
+                    let mut pin_manager = lock_pin_manager(pin_manager).await;
+                    match session.poll_uart(&mut pin_manager, &mut fmt_buffer) {
+                        "" => continue, // nothing received
+                        msg => msg,
                     }
                 }
 
                 // messages from client
-                Either::Second(read_result) => match read_result {
+                Either4::Third(read_result) => match read_result {
                     Ok(0) => break, // EOF received -> close socket and wait for new one
                     Err(embassy_net::tcp::Error::ConnectionReset) => {
                         log::error!("could not receive data from client - connection reset");
@@ -152,7 +269,15 @@ async fn main_loop(stack: &'static Stack<WifiDevice<'static>>, mut pin_manager:
                             let messages =
                                 read_buffer[..last_newline_index].split(|x| *x == ('\n' as u8));
 
-                            if process_messages(messages, &mut socket, &mut pin_manager).await
+                            if process_messages(
+                                messages,
+                                &mut socket,
+                                pin_manager,
+                                config,
+                                aliases,
+                                &mut session,
+                            )
+                            .await
                                 == Err(embassy_net::tcp::Error::ConnectionReset)
                             {
                                 log::error!("could not send message to client - connection reset");
@@ -168,34 +293,68 @@ async fn main_loop(stack: &'static Stack<WifiDevice<'static>>, mut pin_manager:
                         } else {
                             read_pos += len;
                         }
+                        continue;
                     }
                 },
             };
+
+            if socket.write_all(msg.as_bytes()).await
+                == Err(embassy_net::tcp::Error::ConnectionReset)
+            {
+                log::error!("could not send message to client - connection reset");
+                break;
+            }
+            if socket.flush().await == Err(embassy_net::tcp::Error::ConnectionReset) {
+                log::error!("could not send message to client - connection reset");
+                break;
+            }
+        }
+        {
+            let mut pin_manager = lock_pin_manager(pin_manager).await;
+            pin_manager.unwatch_all_pins(client_id);
+            pin_manager.uart_stop_bridge(client_id);
+            session.cancel_frequency_measurements(&mut pin_manager);
         }
         socket.close();
         Timer::after(Duration::from_millis(1000)).await;
         socket.abort();
 
-        log::info!("disconnected!");
+        log::info!("client {}: disconnected!", client_id);
     }
 }
 
 async fn process_messages<'a, I>(
     messages: I,
     socket: &mut TcpSocket<'_>,
-    pin_manager: &mut RushPinManager,
+    pin_manager: &'static RushPinManagerMutex,
+    config: &'static RushConfigMutex,
+    aliases: &'static RushAliasesMutex,
+    session: &mut RushSession,
 ) -> Result<(), embassy_net::tcp::Error>
 where
     I: Iterator<Item = &'a [u8]>,
 {
     for message in messages {
-        let mut fmt_buffer = [0u8; 1024];
-        let response_string = match from_utf8(message) {
-            Err(_) => "could not parse command - conversion to utf8 failed\n",
-            Ok(msg_as_str) => match parse(msg_as_str) {
-                Err(_) => "invalid command\n",
-                Ok((_, parsed_command)) => parsed_command.execute(&mut fmt_buffer, pin_manager),
-            },
+        let mut fmt_buffer = [0u8; 4096]; // big enough for the output of `list` in json
+        if session.is_bridging_uart() {
+            let response_string =
+                bridge_message(message, pin_manager, session, &mut fmt_buffer).await;
+            socket.write_all(response_string.as_bytes()).await?;
+            continue;
+        }
+        let response_string = {
+            // only hold the config, the aliases and the pin manager while executing, not while talking to the client
+            let mut config = config.lock().await;
+            let mut aliases = aliases.lock().await;
+            let mut pin_manager = lock_pin_manager(pin_manager).await;
+            execute_message(
+                message,
+                &mut fmt_buffer,
+                &mut pin_manager,
+                session,
+                &mut config,
+                &mut aliases,
+            )
         };
 
         socket.write_all(response_string.as_bytes()).await?;
@@ -204,3 +363,82 @@ where
     socket.flush().await?;
     Ok(())
 }
+
+// commands answer with a RushResponse, which is written in the format of the client
+fn execute_message<'b>(
+    message: &[u8],
+    fmt_buffer: &'b mut [u8],
+    pin_manager: &mut RushPinManager,
+    session: &mut RushSession,
+    config: &mut RushConfig,
+    aliases: &mut RushAliases,
+) -> &'b str {
+    let mut text_buffer = [0u8; 2048]; // big enough for the output of `list`
+    let message = match from_utf8(message) {
+        Ok(message) => message,
+        Err(err) => {
+            let err = RushError::ParseError {
+                position: err.valid_up_to(),
+                expected: "utf8",
+            };
+            let response = format_error(
+                &mut text_buffer,
+                err,
+                format_args!("could not parse command"),
+            );
+            return format_response(fmt_buffer, session.format, None, "", &response);
+        }
+    };
+
+    // `#[req] [command]` - the request id is sent back with the response
+    let (req, message) = match message.strip_prefix('#') {
+        Some(tagged) if !tagged.is_empty() && !tagged.starts_with(char::is_whitespace) => {
+            session.mark_events = true;
+            let (req, command) = tagged
+                .split_once(char::is_whitespace)
+                .unwrap_or((tagged, ""));
+            (Some(req), command.trim_start())
+        }
+        _ => (None, message),
+    };
+
+    // a command has to use up the whole line - trailing words are an error instead of being ignored
+    let message = message.trim_matches(|c| c == '\r' || c == ' ');
+    let parsed = nom::combinator::all_consuming(|input| parse(input, aliases))(message);
+    let response = match parsed {
+        Err(err) => command_parser::format_parse_error(&mut text_buffer, message, err),
+        Ok((_, parsed_command)) => {
+            parsed_command.execute(&mut text_buffer, pin_manager, session, config, aliases)
+        }
+    };
+    let cmd = message.split_whitespace().next().unwrap_or_default();
+    format_response(fmt_buffer, session.format, req, cmd, &response)
+}
+
+// while the uart is bridged, every line goes to the uart as is - only `~.` on a line of its own ends the bridge
+async fn bridge_message<'b>(
+    message: &[u8],
+    pin_manager: &'static RushPinManagerMutex,
+    session: &mut RushSession,
+    fmt_buffer: &'b mut [u8],
+) -> &'b str {
+    let mut pin_manager = lock_pin_manager(pin_manager).await;
+    let mut text_buffer = [0u8; 128];
+    let response = if message.strip_suffix(b"\r").unwrap_or(message) == b"~." {
+        pin_manager.uart_stop_bridge(session.client_id);
+        session.stop_uart_bridge();
+        RushResponse::new("stopped bridging uart\n")
+    } else {
+        match pin_manager
+            .uart_write(session.client_id, message)
+            .and_then(|()| pin_manager.uart_write(session.client_id, b"\n"))
+        {
+            Ok(()) => RushResponse::new(""),
+            Err(e) => {
+                session.stop_uart_bridge();
+                format_stopped(&mut text_buffer, e, format_args!("stopped bridging uart"))
+            }
+        }
+    };
+    format_response(fmt_buffer, session.format, None, "uart", &response)
+}
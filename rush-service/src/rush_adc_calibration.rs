@@ -0,0 +1,88 @@
+// esp32s3-hal 0.8 has no adc calibration - this applies the values burnt into the efuses at the factory, the way
+// esp_efuse_rtc_calib.c and the line fitting scheme of esp-idf do it. only adc1 at 11 db is used by rush.
+// the init code zeroes the offset of the adc, the reference point gives the gain: `cal_code` raw at `CAL_MILLIVOLTS`
+
+// all calibration fields sit in efuse block 2, which has to be of version 1 to hold them
+const BLK_VERSION_MAJOR: (usize, usize) = (128, 2); // first bit and number of bits
+const ADC1_INIT_CODE_ATTEN: [(usize, usize); 4] = [(149, 8), (157, 6), (163, 6), (169, 6)];
+const ADC1_CAL_VOL_ATTEN3: (usize, usize) = (225, 8);
+
+// the voltage the reference point was measured at
+const CAL_MILLIVOLTS: u32 = 850;
+
+// the analog registers of the sar adc are behind the internal i2c bus of the chip
+const REGI2C_SAR: u8 = 0x69;
+const REGI2C_SAR_HOST_ID: u8 = 1;
+const SAR1_INITIAL_CODE_LOW: (u8, u8, u8) = (0, 7, 0); // register, msb, lsb
+const SAR1_INITIAL_CODE_HIGH: (u8, u8, u8) = (1, 3, 0);
+const SAR1_DREF: (u8, u8, u8) = (2, 6, 4);
+
+// in the rom of the chip - the linker gets its address from rom_functions.x
+extern "C" {
+    fn rom_i2c_writeReg_Mask(block: u8, host_id: u8, reg_add: u8, msb: u8, lsb: u8, data: u8);
+}
+
+#[derive(Clone, Copy)]
+pub struct RushAdcCalibration {
+    init_code: u16,
+    cal_code: u16,
+}
+
+impl RushAdcCalibration {
+    // None on chips that were not calibrated at the factory
+    pub fn from_efuse() -> Option<Self> {
+        if read_block2(BLK_VERSION_MAJOR) != 1 {
+            return None;
+        }
+
+        // each attenuation is stored as the difference to the one before
+        let [diff0, diff1, diff2, diff3] = ADC1_INIT_CODE_ATTEN.map(read_block2);
+        let init_code0 = diff0 + 1850;
+        let init_code1 = diff1 + init_code0 + 90;
+        let init_code2 = diff2 + init_code1;
+        let init_code3 = diff3 + init_code2 + 70;
+        let cal_code = read_block2(ADC1_CAL_VOL_ATTEN3) + 900;
+
+        Some(RushAdcCalibration {
+            init_code: init_code3 as u16,
+            cal_code: cal_code as u16,
+        })
+    }
+
+    // has to run before sampling - the hal driver leaves the init code alone, but does not set it either
+    pub fn apply(&self) {
+        let [high, low] = self.init_code.to_be_bytes();
+        write_regi2c(SAR1_DREF, 4);
+        write_regi2c(SAR1_INITIAL_CODE_HIGH, high);
+        write_regi2c(SAR1_INITIAL_CODE_LOW, low);
+    }
+
+    pub fn millivolts(&self, raw: u16) -> u32 {
+        raw as u32 * CAL_MILLIVOLTS / self.cal_code as u32
+    }
+}
+
+// a field of efuse block 2 - fields may cross the border between two words
+fn read_block2((first_bit, bits): (usize, usize)) -> u32 {
+    // safety: the read registers of the efuse controller are never written
+    let efuse = unsafe { &*esp32s3_hal::peripherals::EFUSE::PTR };
+    let words = [
+        efuse.rd_sys_part1_data0.read().bits(),
+        efuse.rd_sys_part1_data1.read().bits(),
+        efuse.rd_sys_part1_data2.read().bits(),
+        efuse.rd_sys_part1_data3.read().bits(),
+        efuse.rd_sys_part1_data4.read().bits(),
+        efuse.rd_sys_part1_data5.read().bits(),
+        efuse.rd_sys_part1_data6.read().bits(),
+        efuse.rd_sys_part1_data7.read().bits(),
+    ];
+    let word = first_bit / 32;
+    let low = words[word] as u64;
+    let high = words.get(word + 1).copied().unwrap_or_default() as u64;
+    ((high << 32 | low) >> (first_bit % 32) & ((1 << bits) - 1)) as u32
+}
+
+fn write_regi2c((reg_add, msb, lsb): (u8, u8, u8), data: u8) {
+    // safety: only touches the analog registers of adc1, which belongs to the pin manager
+    unsafe { rom_i2c_writeReg_Mask(REGI2C_SAR, REGI2C_SAR_HOST_ID, reg_add, msb, lsb, data) };
+}
@@ -0,0 +1,159 @@
+// which pins may be used depends on the module the esp32s3 sits on - flash and psram take some pins for good,
+// others decide how the chip boots or carry usb. boards of known development kits also name their pins.
+// the board is chosen with `config set board [name]`, which takes effect after the next reboot
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RushPinPolicy {
+    Allowed,
+    Warn, // needs `force` - the pin works, but using it can keep the board from booting or from talking usb
+    Forbidden, // using the pin crashes the board
+}
+
+pub struct RushBoard {
+    pub name: &'static str,
+    pub description: &'static str,
+    pins: &'static [(u8, RushPinPolicy, &'static str)], // pins that are not listed are allowed - the text says why
+    aliases: &'static [(&'static str, &'static str)], // names of the board's pins, e.g. ("led", "gpio.21")
+}
+
+impl RushBoard {
+    pub const ALL: [&'static RushBoard; 4] = [
+        &BOARD_ESP32S3,
+        &BOARD_ESP32S3_OCTAL,
+        &BOARD_DEVKITC_1,
+        &BOARD_XIAO_ESP32S3,
+    ];
+
+    pub fn default() -> &'static RushBoard {
+        RushBoard::ALL[0]
+    }
+
+    pub fn find(name: &str) -> Option<&'static RushBoard> {
+        RushBoard::ALL.into_iter().find(|board| board.name == name)
+    }
+
+    // the policy of the pin and the reason for it - the reason is empty for allowed pins
+    pub fn pin_policy(&self, pin: u8) -> (RushPinPolicy, &'static str) {
+        self.pins
+            .iter()
+            .find(|(policy_pin, _, _)| *policy_pin == pin)
+            .map(|(_, policy, reason)| (*policy, *reason))
+            .unwrap_or((RushPinPolicy::Allowed, ""))
+    }
+
+    pub fn protected_pins(&self) -> impl Iterator<Item = (u8, RushPinPolicy, &'static str)> {
+        self.pins.iter().copied()
+    }
+
+    // resolved like the aliases of the user, which take precedence
+    pub fn aliases(&self) -> impl Iterator<Item = (&'static str, &'static str)> {
+        self.aliases.iter().copied()
+    }
+}
+
+const FLASH: &str = "pin is connected to the spi flash or psram - using it would crash the board";
+const OCTAL_PSRAM: &str = "pin is connected to the octal psram - using it would crash the board";
+const STRAPPING: &str =
+    "pin is a strapping pin and decides how the board boots - add force to use it anyway";
+const USB: &str =
+    "pin carries usb, which stops working when it is used - add force to use it anyway";
+
+// modules with quad spi flash and psram
+const QUAD_PINS: &[(u8, RushPinPolicy, &str)] = &[
+    (0, RushPinPolicy::Warn, STRAPPING),
+    (3, RushPinPolicy::Warn, STRAPPING),
+    (19, RushPinPolicy::Warn, USB),
+    (20, RushPinPolicy::Warn, USB),
+    (26, RushPinPolicy::Forbidden, FLASH),
+    (27, RushPinPolicy::Forbidden, FLASH),
+    (28, RushPinPolicy::Forbidden, FLASH),
+    (29, RushPinPolicy::Forbidden, FLASH),
+    (30, RushPinPolicy::Forbidden, FLASH),
+    (31, RushPinPolicy::Forbidden, FLASH),
+    (32, RushPinPolicy::Forbidden, FLASH),
+    (45, RushPinPolicy::Warn, STRAPPING),
+    (46, RushPinPolicy::Warn, STRAPPING),
+];
+
+// octal psram needs five more data lines
+const OCTAL_PINS: &[(u8, RushPinPolicy, &str)] = &[
+    (0, RushPinPolicy::Warn, STRAPPING),
+    (3, RushPinPolicy::Warn, STRAPPING),
+    (19, RushPinPolicy::Warn, USB),
+    (20, RushPinPolicy::Warn, USB),
+    (26, RushPinPolicy::Forbidden, FLASH),
+    (27, RushPinPolicy::Forbidden, FLASH),
+    (28, RushPinPolicy::Forbidden, FLASH),
+    (29, RushPinPolicy::Forbidden, FLASH),
+    (30, RushPinPolicy::Forbidden, FLASH),
+    (31, RushPinPolicy::Forbidden, FLASH),
+    (32, RushPinPolicy::Forbidden, FLASH),
+    (33, RushPinPolicy::Forbidden, OCTAL_PSRAM),
+    (34, RushPinPolicy::Forbidden, OCTAL_PSRAM),
+    (35, RushPinPolicy::Forbidden, OCTAL_PSRAM),
+    (36, RushPinPolicy::Forbidden, OCTAL_PSRAM),
+    (37, RushPinPolicy::Forbidden, OCTAL_PSRAM),
+    (45, RushPinPolicy::Warn, STRAPPING),
+    (46, RushPinPolicy::Warn, STRAPPING),
+];
+
+const BOARD_ESP32S3: RushBoard = RushBoard {
+    name: "esp32s3",
+    description: "modules with quad spi flash and psram, e.g. esp32-s3-wroom-1 n8 or n8r2",
+    pins: QUAD_PINS,
+    aliases: &[],
+};
+
+const BOARD_ESP32S3_OCTAL: RushBoard = RushBoard {
+    name: "esp32s3-octal",
+    description: "modules with octal psram, e.g. esp32-s3-wroom-1 n8r8 or n16r8",
+    pins: OCTAL_PINS,
+    aliases: &[],
+};
+
+// the devkit is sold with quad and octal modules - the octal pins cover both
+const BOARD_DEVKITC_1: RushBoard = RushBoard {
+    name: "devkitc-1",
+    description: "esp32-s3-devkitc-1 - the rgb led sits on gpio.38 since v1.1, on gpio.48 before",
+    pins: OCTAL_PINS,
+    aliases: &[
+        ("boot", "gpio.0"),
+        ("rgb", "gpio.38"),
+        ("tx", "gpio.43"),
+        ("rx", "gpio.44"),
+    ],
+};
+
+// the pads are named after their arduino pins - the orange led is active low
+const BOARD_XIAO_ESP32S3: RushBoard = RushBoard {
+    name: "xiao-esp32s3",
+    description: "seeed studio xiao esp32s3 with 8mb octal psram",
+    pins: OCTAL_PINS,
+    aliases: &[
+        ("d0", "gpio.1"),
+        ("d1", "gpio.2"),
+        ("d2", "gpio.3"),
+        ("d3", "gpio.4"),
+        ("d4", "gpio.5"),
+        ("d5", "gpio.6"),
+        ("d6", "gpio.43"),
+        ("d7", "gpio.44"),
+        ("d8", "gpio.7"),
+        ("d9", "gpio.8"),
+        ("d10", "gpio.9"),
+        ("sda", "gpio.5"),
+        ("scl", "gpio.6"),
+        ("led", "gpio.21"),
+        ("boot", "gpio.0"),
+    ],
+};
+
+impl core::fmt::Display for RushPinPolicy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.pad(match self {
+            RushPinPolicy::Allowed => "allowed",
+            RushPinPolicy::Warn => "warn",
+            RushPinPolicy::Forbidden => "forbidden",
+        })
+    }
+}
@@ -0,0 +1,378 @@
+use crate::rush_board::RushBoard;
+use crate::rush_error::RushError;
+
+use core::str::from_utf8;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use esp32s3_hal::Rng;
+use esp_storage::FlashStorage;
+use rush_common::rush_config_store::{RushConfigStore, MAX_KEY_LEN};
+use stackfmt::fmt_truncate;
+
+pub type RushConfigMutex = Mutex<CriticalSectionRawMutex, RushConfig>;
+
+// the rushcfg partition - has to match partitions.csv
+const CONFIG_FLASH_OFFSET: u32 = 0x3F_0000;
+const CONFIG_FLASH_SIZE: u32 = 0x1_0000;
+
+const DEFAULT_ADDRESS: [u8; 4] = [192, 168, 2, 1];
+const DEFAULT_PORT: u16 = 2000;
+const DEFAULT_STA_RETRY_MS: u32 = 5000;
+
+// pin aliases share the store with the settings - the prefix keeps their keys apart
+const ALIAS_KEY_PREFIX: &str = "a.";
+pub const MAX_ALIAS_LEN: usize = MAX_KEY_LEN - ALIAS_KEY_PREFIX.len();
+
+// the password generated on first boot - kept apart from `password`, so a reset falls back to it
+const DEFAULT_PASSWORD_KEY: &str = "password.default";
+const DEFAULT_PASSWORD_CHARS: &[u8] = b"abcdefghijkmnpqrstuvwxyz23456789";
+const DEFAULT_PASSWORD_LEN: usize = 12; // 60 random bits
+
+#[derive(Clone, Copy, Debug)]
+pub enum RushConfigKey {
+    Ssid,
+    Password,
+    Address,
+    Port,
+    Board,
+    StaSsid,
+    StaPassword,
+    StaRetryMs,
+}
+
+impl RushConfigKey {
+    pub const ALL: [RushConfigKey; 8] = [
+        RushConfigKey::Ssid,
+        RushConfigKey::Password,
+        RushConfigKey::Address,
+        RushConfigKey::Port,
+        RushConfigKey::Board,
+        RushConfigKey::StaSsid,
+        RushConfigKey::StaPassword,
+        RushConfigKey::StaRetryMs,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RushConfigKey::Ssid => "ssid",
+            RushConfigKey::Password => "password",
+            RushConfigKey::Address => "address",
+            RushConfigKey::Port => "port",
+            RushConfigKey::Board => "board",
+            RushConfigKey::StaSsid => "sta_ssid",
+            RushConfigKey::StaPassword => "sta_password",
+            RushConfigKey::StaRetryMs => "sta_retry_ms",
+        }
+    }
+
+    // None for the password - its default is generated on first boot, see generate_password()
+    fn default_value(&self) -> Option<&'static str> {
+        match self {
+            RushConfigKey::Ssid => Some("rush"),
+            RushConfigKey::Password => None,
+            RushConfigKey::Address => Some("192.168.2.1"),
+            RushConfigKey::Port => Some("2000"),
+            RushConfigKey::Board => Some(RushBoard::default().name),
+            // no network to join - the board opens its own
+            RushConfigKey::StaSsid => Some(""),
+            RushConfigKey::StaPassword => Some(""),
+            RushConfigKey::StaRetryMs => Some("5000"),
+        }
+    }
+
+    // secret values are never sent back to a client
+    pub fn is_secret(&self) -> bool {
+        matches!(self, RushConfigKey::Password | RushConfigKey::StaPassword)
+    }
+
+    fn validate<'a>(&self, value: &'a str) -> Result<(), RushError> {
+        match self {
+            RushConfigKey::Ssid if value.is_empty() || value.len() > 32 => {
+                Err(RushError::InvalidValue("ssid must be 1 to 32 bytes long"))
+            }
+            // wpa2 only accepts passphrases of 8 to 63 printable ascii characters
+            RushConfigKey::Password if value.len() < 8 || value.len() > 63 => Err(
+                RushError::InvalidValue("password must be 8 to 63 characters long"),
+            ),
+            RushConfigKey::Password if !value.bytes().all(|c| (b' '..=b'~').contains(&c)) => Err(
+                RushError::InvalidValue("password may only contain printable ascii characters"),
+            ),
+            RushConfigKey::Address => match parse_address(value) {
+                Some([_, _, _, host]) if host != 0 && host != 255 => Ok(()),
+                _ => Err(RushError::InvalidValue(
+                    "address must look like 192.168.2.1 and must not end in .0 or .255",
+                )),
+            },
+            RushConfigKey::Port => match value.parse::<u16>() {
+                Ok(port) if port != 0 => Ok(()),
+                _ => Err(RushError::InvalidValue(
+                    "port must be a number from 1 to 65535",
+                )),
+            },
+            RushConfigKey::StaSsid if value.len() > 32 => Err(RushError::InvalidValue(
+                "sta_ssid must be at most 32 bytes long",
+            )),
+            // empty joins an open network
+            RushConfigKey::StaPassword
+                if !value.is_empty() && (value.len() < 8 || value.len() > 63) =>
+            {
+                Err(RushError::InvalidValue(
+                    "sta_password must be empty or 8 to 63 characters long",
+                ))
+            }
+            RushConfigKey::StaPassword if !value.bytes().all(|c| (b' '..=b'~').contains(&c)) => {
+                Err(RushError::InvalidValue(
+                    "sta_password may only contain printable ascii characters",
+                ))
+            }
+            RushConfigKey::StaRetryMs => match value.parse::<u32>() {
+                Ok(500..=600_000) => Ok(()),
+                _ => Err(RushError::InvalidValue(
+                    "sta_retry_ms must be a number from 500 to 600000",
+                )),
+            },
+            RushConfigKey::Board if RushBoard::find(value).is_none() => Err(
+                RushError::InvalidValue("board must be one of the names listed by `board list`"),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl core::fmt::Display for RushConfigKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+// settings that have to survive a reboot - changes only take effect after the next reset,
+// as wifi and sockets are set up once while booting
+pub struct RushConfig {
+    store: Option<RushConfigStore<FlashStorage>>,
+    default_password: heapless::String<24>,
+}
+
+impl RushConfig {
+    // falls back to the defaults if the flash can not be used - the default password then changes with every boot
+    pub fn load(rng: &mut Rng) -> Self {
+        let store = match RushConfigStore::mount(
+            FlashStorage::new(),
+            CONFIG_FLASH_OFFSET,
+            CONFIG_FLASH_SIZE,
+        ) {
+            Ok(store) => Some(store),
+            Err(e) => {
+                log::error!("could not mount config store: {} - using defaults", e);
+                None
+            }
+        };
+        let mut config = RushConfig {
+            store,
+            default_password: heapless::String::new(),
+        };
+        let stored = config
+            .store
+            .as_ref()
+            .and_then(|store| store.get(DEFAULT_PASSWORD_KEY));
+        match stored {
+            Some(password) => {
+                config.default_password.push_str(password).ok();
+            }
+            None => {
+                config.default_password = generate_password(rng);
+                config.store_default_password();
+            }
+        }
+        config
+    }
+
+    fn store_default_password(&mut self) {
+        if let Some(store) = &mut self.store {
+            if let Err(e) = store.set(DEFAULT_PASSWORD_KEY, &self.default_password) {
+                log::error!("could not store the default password: {}", e);
+            }
+        }
+    }
+
+    pub fn get(&self, key: RushConfigKey) -> &str {
+        let stored = self.store.as_ref().and_then(|store| store.get(key.name()));
+        match (stored, key.default_value()) {
+            (Some(value), _) => value,
+            (None, Some(default_value)) => default_value,
+            (None, None) => &self.default_password,
+        }
+    }
+
+    // true while the key was never set or was reset
+    pub fn is_default(&self, key: RushConfigKey) -> bool {
+        match &self.store {
+            Some(store) => store.get(key.name()).is_none(),
+            None => true,
+        }
+    }
+
+    pub fn set<'a>(&mut self, key: RushConfigKey, value: &'a str) -> Result<(), RushError> {
+        key.validate(value)?;
+        match &mut self.store {
+            Some(store) => store.set(key.name(), value),
+            None => Err(RushError::Storage("config store is not available")),
+        }
+    }
+
+    pub fn reset(&mut self, key: RushConfigKey) -> Result<(), RushError> {
+        match &mut self.store {
+            Some(store) => store.remove(key.name()),
+            None => Err(RushError::Storage("config store is not available")),
+        }
+    }
+
+    // keeps the default password, which no client could find out after a reset
+    pub fn reset_all(&mut self) -> Result<(), RushError> {
+        match &mut self.store {
+            Some(store) => store.clear()?,
+            None => return Err(RushError::Storage("config store is not available")),
+        }
+        self.store_default_password();
+        Ok(())
+    }
+
+    // the value as it may be shown to a client
+    pub fn display_value(&self, key: RushConfigKey) -> &str {
+        if key.is_secret() {
+            "********"
+        } else {
+            self.get(key)
+        }
+    }
+
+    pub fn ssid(&self) -> &str {
+        self.get(RushConfigKey::Ssid)
+    }
+
+    pub fn password(&self) -> &str {
+        self.get(RushConfigKey::Password)
+    }
+
+    pub fn address(&self) -> [u8; 4] {
+        parse_address(self.get(RushConfigKey::Address)).unwrap_or(DEFAULT_ADDRESS)
+    }
+
+    pub fn port(&self) -> u16 {
+        self.get(RushConfigKey::Port)
+            .parse()
+            .unwrap_or(DEFAULT_PORT)
+    }
+
+    // None while no network is configured - the board then opens its own
+    pub fn sta_ssid(&self) -> Option<&str> {
+        match self.get(RushConfigKey::StaSsid) {
+            "" => None,
+            ssid => Some(ssid),
+        }
+    }
+
+    pub fn sta_password(&self) -> &str {
+        self.get(RushConfigKey::StaPassword)
+    }
+
+    // how long a station waits before it tries to join its network again
+    pub fn sta_retry_ms(&self) -> u32 {
+        self.get(RushConfigKey::StaRetryMs)
+            .parse()
+            .unwrap_or(DEFAULT_STA_RETRY_MS)
+    }
+
+    pub fn board(&self) -> &'static RushBoard {
+        RushBoard::find(self.get(RushConfigKey::Board)).unwrap_or(RushBoard::default())
+    }
+
+    // name and target of every alias the user defined
+    pub fn aliases(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.store
+            .iter()
+            .flat_map(|store| store.iter())
+            .filter_map(|(key, value)| key.strip_prefix(ALIAS_KEY_PREFIX).map(|name| (name, value)))
+    }
+
+    // the target is stored as the client typed it - it is checked by the command parser, which knows its syntax
+    pub fn set_alias(&mut self, name: &str, target: &str) -> Result<(), RushError> {
+        let key = alias_key(name)?;
+        match &mut self.store {
+            Some(store) => store.set(&key, target),
+            None => Err(RushError::Storage("config store is not available")),
+        }
+    }
+
+    pub fn remove_alias(&mut self, name: &str) -> Result<(), RushError> {
+        let key = alias_key(name)?;
+        match &mut self.store {
+            Some(store) if store.get(&key).is_none() => {
+                Err(RushError::InvalidValue("alias does not exist"))
+            }
+            Some(store) => store.remove(&key),
+            None => Err(RushError::Storage("config store is not available")),
+        }
+    }
+
+    pub fn list<'b>(&self, fmt_buffer: &'b mut [u8]) -> &'b str {
+        let mut len = 0;
+        for key in RushConfigKey::ALL {
+            len += fmt_truncate(
+                &mut fmt_buffer[len..],
+                format_args!("{:<12}= {}\n", key, self.display_value(key)),
+            )
+            .len();
+        }
+        from_utf8(&fmt_buffer[..len]).unwrap_or_default()
+    }
+}
+
+// every board gets a random password of its own until one is set, e.g. rush-k7mw2xq9fa3e. it is logged on the
+// serial console at boot, as no client can read it back. nothing that is broadcast, like the mac, may go into it
+fn generate_password(rng: &mut Rng) -> heapless::String<24> {
+    enable_entropy_source();
+    let mut password = heapless::String::new();
+    password.push_str("rush-").ok();
+    for _ in 0..DEFAULT_PASSWORD_LEN {
+        // 32 characters, so every one of them is equally likely
+        let index = rng.random() as usize % DEFAULT_PASSWORD_CHARS.len();
+        password.push(DEFAULT_PASSWORD_CHARS[index] as char).ok();
+    }
+    password
+}
+
+// the rng only yields random numbers while it has a source of noise - the radio is not on yet at boot,
+// so this turns on the 8 mhz clock it samples, like esp_hal::rng::TrngSource of later hal versions does
+fn enable_entropy_source() {
+    const WIFI_CLK_RNG_EN: u32 = 1 << 15;
+    // safety: wifi_clk_en and clk_conf are only touched here before the wifi driver starts
+    let apb_ctrl = unsafe { &*esp32s3_hal::peripherals::APB_CTRL::PTR };
+    apb_ctrl
+        .wifi_clk_en
+        .modify(|r, w| unsafe { w.bits(r.bits() | WIFI_CLK_RNG_EN) });
+    let rtc_cntl = unsafe { &*esp32s3_hal::peripherals::RTC_CNTL::PTR };
+    rtc_cntl.clk_conf.modify(|_, w| w.dig_clk8m_en().set_bit());
+}
+
+fn alias_key(name: &str) -> Result<heapless::String<MAX_KEY_LEN>, RushError> {
+    let mut key = heapless::String::new();
+    key.push_str(ALIAS_KEY_PREFIX).ok();
+    match key.push_str(name) {
+        Ok(()) => Ok(key),
+        Err(()) => Err(RushError::InvalidValue(
+            "alias names can be at most 14 characters long",
+        )),
+    }
+}
+
+fn parse_address(value: &str) -> Option<[u8; 4]> {
+    let mut address = [0u8; 4];
+    let mut octets = value.split('.');
+    for octet in address.iter_mut() {
+        *octet = octets.next()?.parse().ok()?;
+    }
+    match octets.next() {
+        None => Some(address),
+        Some(_) => None,
+    }
+}
@@ -0,0 +1,24 @@
+pub use rush_common::rush_error::RushError;
+
+use crate::rush_response::{fmt_split, RushResponse};
+use stackfmt::fmt_truncate;
+
+// `[context]: [error]` - the response adds the code
+pub fn format_error<'b>(
+    fmt_buffer: &'b mut [u8],
+    err: RushError,
+    context: core::fmt::Arguments,
+) -> RushResponse<'b> {
+    let message = fmt_truncate(fmt_buffer, format_args!("{}: {}", context, err));
+    RushResponse::failed(err, message)
+}
+
+// an error that ended something the client asked for - the error and what it ended
+pub fn format_stopped<'b>(
+    fmt_buffer: &'b mut [u8],
+    err: RushError,
+    consequence: core::fmt::Arguments,
+) -> RushResponse<'b> {
+    let (message, rest) = fmt_split(fmt_buffer, format_args!("{}", err));
+    RushResponse::failed(err, message).stopped(fmt_truncate(rest, consequence))
+}
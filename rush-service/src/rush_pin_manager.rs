@@ -1,27 +1,203 @@
+use core::cell::RefCell;
+use core::fmt;
+use core::future::pending;
+use core::str::from_utf8;
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use embassy_futures::select::{select, select_array, Either};
+use embassy_futures::yield_now;
+use embassy_sync::blocking_mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+use embassy_sync::pubsub::PubSubChannel;
+use embassy_sync::signal::Signal;
 use embassy_time::{Duration, Timer};
+use embedded_hal_async::digital::Wait;
 use enum_dispatch::enum_dispatch;
 use esp32s3_hal;
+use esp32s3_hal::adc::{AdcConfig, Attenuation, ADC, ADC1};
+use esp32s3_hal::clock::Clocks;
+use esp32s3_hal::ehal::adc::{Channel, OneShot};
+use esp32s3_hal::ehal::blocking::i2c::{Read, Write, WriteRead};
+use esp32s3_hal::ehal::blocking::spi::Transfer;
 use esp32s3_hal::ehal::digital::v2::PinState;
 use esp32s3_hal::gpio;
+use esp32s3_hal::i2c::I2C;
+use esp32s3_hal::ledc::channel::{self, ChannelIFace};
+use esp32s3_hal::ledc::timer::{self, TimerIFace};
+use esp32s3_hal::ledc::{LSGlobalClkSource, LowSpeed, LEDC};
+use esp32s3_hal::pcnt::channel::{CtrlMode, EdgeMode, PcntSource};
+use esp32s3_hal::pcnt::unit;
 use esp32s3_hal::prelude::_embedded_hal_digital_v2_OutputPin;
+use esp32s3_hal::prelude::_embedded_hal_digital_v2_StatefulOutputPin;
+use esp32s3_hal::prelude::_fugit_RateExtU32;
 use esp32s3_hal::prelude::eh1::_embedded_hal_digital_blocking_InputPin;
+use esp32s3_hal::spi::{Spi, SpiMode};
+use esp32s3_hal::system::PeripheralClockControl;
+use esp32s3_hal::uart::{config::Config, TxRxPins, Uart};
 use stackfmt::fmt_truncate;
 
+use crate::rush_adc_calibration::RushAdcCalibration;
+use crate::rush_board::{RushBoard, RushPinPolicy};
+use crate::rush_error::RushError;
+use crate::rush_routed_pin::RushRoutedPin;
+use crate::rush_touch_sensor::{RushTouchSensor, TOUCH_PINS};
+
+// full scale of the 12 bit adc at 11 db attenuation - only used on chips without efuse calibration, whose millivolts
+// are converted linearly from the nominal range and are reported as uncalibrated
+const ADC_MAX_RAW: u32 = 4095;
+const ADC_MAX_MILLIVOLTS: u32 = 3100;
+
+// the ledc has 8 channels, but only 4 timers - so at most 4 different pwm frequencies at a time
+const PWM_CHANNELS: [channel::Number; 8] = [
+    channel::Number::Channel0,
+    channel::Number::Channel1,
+    channel::Number::Channel2,
+    channel::Number::Channel3,
+    channel::Number::Channel4,
+    channel::Number::Channel5,
+    channel::Number::Channel6,
+    channel::Number::Channel7,
+];
+const PWM_TIMERS: [timer::Number; 4] = [
+    timer::Number::Timer0,
+    timer::Number::Timer1,
+    timer::Number::Timer2,
+    timer::Number::Timer3,
+];
+const PWM_CLOCK_HZ: u64 = 80_000_000; // apb clock
+
+// the pulse counter units only count to 16 bit - every time a unit reaches the limit it starts over
+// and the interrupt adds one to its overflow count
+const PCNT_UNITS: [unit::Number; 4] = [
+    unit::Number::Unit0,
+    unit::Number::Unit1,
+    unit::Number::Unit2,
+    unit::Number::Unit3,
+];
+const PCNT_HIGH_LIMIT: i16 = 30_000;
+static PCNT_OVERFLOWS: [AtomicU32; 4] = [
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+];
+// shared with the interrupt handler, which has to clear the events of the units
+static PCNT_DRIVER: blocking_mutex::Mutex<
+    CriticalSectionRawMutex,
+    RefCell<Option<esp32s3_hal::pcnt::PCNT<'static>>>,
+> = blocking_mutex::Mutex::new(RefCell::new(None));
+
+// touch pads do not interrupt, so watched pads are measured again after this interval
+const TOUCH_POLL_INTERVAL: Duration = Duration::from_millis(50);
+// the owner check_usable() is asked for by touch reads - the only use a watched touch pad still allows
+const TOUCH_OWNER: &str = "touch";
+
+// every client gets one bit in PinManagerCompoundPin::watchers, so there can be at most 8
+pub const MAX_CLIENTS: usize = 4;
+
+pub type RushPinManagerMutex = Mutex<CriticalSectionRawMutex, RushPinManager>;
+
+// events of watched pins are published to every client - each client filters for the pins it watches
+pub static PIN_EVENTS: PubSubChannel<CriticalSectionRawMutex, RushPinEvent, 16, MAX_CLIENTS, 1> =
+    PubSubChannel::new();
+
+// watch_pins() holds the pin manager while it waits for edges - these tell it to let go
+static LOCK_WAITERS: AtomicU8 = AtomicU8::new(0);
+static LOCK_REQUESTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+#[derive(Debug, Clone, Copy)]
+pub enum RushPinEvent {
+    Changed { pin: u8, state: bool },
+    Pulse { pin: u8, state: bool }, // the pin went to !state and back to state before it could be read
+    Stopped { pin: u8, reason: RushError },
+    TouchStopped { pin: u8, reason: RushError }, // like Stopped, but for a pad watched for touches
+    Touched { pin: u8, value: u32, touched: bool }, // the value of a watched touch pad crossed its threshold
+}
+
+#[derive(Clone, Copy)]
+struct PwmChannel {
+    pin: u8,
+    timer: usize, // index into PWM_TIMERS
+}
+
+#[derive(Clone, Copy)]
+struct PwmTimer {
+    frequency_hz: u32,
+    channels: u8, // number of channels running on this timer
+}
+
+#[derive(Clone, Copy)]
+struct I2cBus {
+    sda: u8,
+    scl: u8,
+    frequency_hz: u32,
+}
+
+#[derive(Clone, Copy)]
+struct SpiBus {
+    sck: u8,
+    mosi: u8,
+    miso: u8,
+    cs: u8,
+    mode: SpiMode,
+    frequency_hz: u32,
+}
+
+#[derive(Clone, Copy)]
+struct UartBus {
+    tx: u8,
+    rx: u8,
+    bridged_by: Option<u8>, // client id - only one client can own the received bytes
+}
+
 struct PinManagerCompoundPin {
     pin: Option<RushAnyPin>,
-    last_state_if_watched: Option<bool>,
+    claimed_by: Option<&'static str>, // Some while a bus or counter uses the pin - names it for list_pins()
+    last_state_if_watched: Option<bool>, // Some while at least one client watches the pin
+    touch_threshold: Option<u32>, // Some while the pin is watched as a touch pad - the state is then `touched`
+    watchers: u8,                 // one bit per client id
+    last_known_state: Option<bool>, // updated on every read, write and watch - reported by list_pins()
 }
 
 pub struct RushPinManager {
     pins: [PinManagerCompoundPin; 49],
     none_pin: Option<RushAnyPin>, // used inside get_pin() if index is out of bounds
     next_pin_to_poll: u8,
+    adc1: ADC1, // adc2 is used by the wifi driver
+    adc1_calibration: Option<RushAdcCalibration>,
+    ledc: esp32s3_hal::peripherals::LEDC,
+    peripheral_clock_control: PeripheralClockControl,
+    clocks: &'static Clocks<'static>,
+    pwm_channels: [Option<PwmChannel>; 8],
+    pwm_timers: [Option<PwmTimer>; 4],
+    i2c0: esp32s3_hal::peripherals::I2C0,
+    i2c_bus: Option<I2cBus>,
+    spi2: esp32s3_hal::peripherals::SPI2,
+    spi3: esp32s3_hal::peripherals::SPI3,
+    spi_buses: [Option<SpiBus>; 2], // spi.2 and spi.3
+    uart1: esp32s3_hal::peripherals::UART1,
+    uart_bus: Option<UartBus>,
+    pcnt_units: [Option<u8>; 4], // pin counted by each unit
+    touch_sensor: RushTouchSensor,
+    board: &'static RushBoard, // decides which pins are protected
 }
 
 impl RushPinManager {
     #[rustfmt::skip]
-    pub fn new(pins: esp32s3_hal::soc::gpio::Pins) -> Self {
-        let mut pin_array = [(); 49].map(|_| PinManagerCompoundPin { pin: Option::<RushAnyPin>::None, last_state_if_watched: Option::None} );
+    pub fn new(
+        pins: esp32s3_hal::soc::gpio::Pins,
+        adc1: ADC1,
+        ledc: esp32s3_hal::peripherals::LEDC,
+        i2c0: esp32s3_hal::peripherals::I2C0,
+        spi2: esp32s3_hal::peripherals::SPI2,
+        spi3: esp32s3_hal::peripherals::SPI3,
+        uart1: esp32s3_hal::peripherals::UART1,
+        pcnt: esp32s3_hal::peripherals::PCNT,
+        mut peripheral_clock_control: PeripheralClockControl,
+        clocks: &'static Clocks<'static>,
+        board: &'static RushBoard,
+    ) -> Self {
+        let mut pin_array = [(); 49].map(|_| PinManagerCompoundPin { pin: Option::<RushAnyPin>::None, claimed_by: Option::None, last_state_if_watched: Option::None, touch_threshold: Option::None, watchers: 0, last_known_state: Option::None } );
 
         pin_array[ 0].pin = Some(RushSinglePin::UnknownAnalogPin(pins.gpio0 ).into());
         pin_array[ 1].pin = Some(RushSinglePin::UnknownAnalogPin(pins.gpio1 ).into());
@@ -70,7 +246,36 @@ impl RushPinManager {
         pin_array[47].pin = Some(RushSinglePin::UnknownDigitalPin(pins.gpio47).into());
         pin_array[48].pin = Some(RushSinglePin::UnknownDigitalPin(pins.gpio48).into());
 
-        RushPinManager { pins: pin_array, none_pin: Option::<RushAnyPin>::None, next_pin_to_poll: 0 }
+        let pcnt = esp32s3_hal::pcnt::PCNT::new(pcnt, &mut peripheral_clock_control);
+        PCNT_DRIVER.lock(|pcnt_driver| pcnt_driver.replace(Some(pcnt)));
+
+        let adc1_calibration = RushAdcCalibration::from_efuse();
+        if adc1_calibration.is_none() {
+            log::warn!("this chip has no adc calibration in its efuses - millivolts will be uncalibrated");
+        }
+
+        RushPinManager {
+            pins: pin_array,
+            none_pin: Option::<RushAnyPin>::None,
+            next_pin_to_poll: 0,
+            adc1,
+            adc1_calibration,
+            ledc,
+            peripheral_clock_control,
+            clocks,
+            pwm_channels: [None; 8],
+            pwm_timers: [None; 4],
+            i2c0,
+            i2c_bus: None,
+            spi2,
+            spi3,
+            spi_buses: [None; 2],
+            uart1,
+            uart_bus: None,
+            pcnt_units: [None; 4],
+            touch_sensor: RushTouchSensor::new(),
+            board,
+        }
     }
 
     pub fn get_pin(&mut self, pin: u8) -> &mut Option<RushAnyPin> {
@@ -81,65 +286,1170 @@ impl RushPinManager {
         &mut self.none_pin
     }
 
-    pub fn watch_pin<'a, 'b>(&'a mut self, pin: u8) -> Result<bool, &'b str> {
-        let current_state = self.get_pin(pin).to_input().read_state()?;
-        self.pins[pin as usize].last_state_if_watched = Some(current_state);
+    // output pins report the level they are driving and keep their mode - only pins of unknown or analog
+    // mode are switched to input, unless `as_input` explicitly asks for the switch
+    pub fn read_pin<'a>(
+        &'a mut self,
+        pin: u8,
+        as_input: bool,
+        force: bool,
+    ) -> Result<bool, RushError> {
+        self.check_usable(pin, None, force)?;
+        if self.pwm_channel(pin).is_some() {
+            if !as_input {
+                return Err(RushError::WrongMode(
+                    "pin is driven by pwm - stop it with `pwm gpio.N off` or read it as input",
+                ));
+            }
+            self.release_pwm(pin);
+        }
+        let any_pin = self.get_pin(pin);
+        if as_input
+            || matches!(
+                any_pin.mode(),
+                Some(RushPinMode::Unknown | RushPinMode::Analog)
+            )
+        {
+            any_pin.to_input();
+        }
+        let current_state = any_pin.read_state()?;
+        self.pins[pin as usize].last_known_state = Some(current_state);
         Ok(current_state)
     }
 
-    pub fn unwatch_pin<'a, 'b>(&'a mut self, pin: u8) -> Result<(), &'b str> {
+    // switches the pin to analog mode and samples it with adc1 - returns the raw value, the millivolts and whether
+    // those are calibrated
+    pub fn read_analog_pin<'a>(
+        &'a mut self,
+        pin: u8,
+        force: bool,
+    ) -> Result<(u16, u32, bool), RushError> {
+        match pin {
+            1..=10 => (),
+            11..=20 => {
+                return Err(RushError::Busy(
+                    "adc2 is used by the wifi driver - only gpio.1 to gpio.10 can be sampled",
+                ))
+            }
+            _ => return Err(RushError::NoSuchPin("pin has no adc channel")),
+        }
+        self.check_usable(pin, None, force)?;
+        self.release_pwm(pin);
+
+        if let Some(calibration) = &self.adc1_calibration {
+            calibration.apply();
+        }
+        let compound_pin = &mut self.pins[pin as usize];
+        let raw = match compound_pin.pin.to_mode(RushPinMode::Analog).take() {
+            None => Err(RushError::NoSuchPin("pin does not exist")),
+            Some(any_pin) => {
+                let (any_pin, raw) = any_pin.read_adc1(&mut self.adc1);
+                compound_pin.pin = Some(any_pin);
+                raw
+            }
+        }?;
+        compound_pin.last_known_state = None; // the digital level is unknown while the pin is analog
+
+        Ok(match &self.adc1_calibration {
+            Some(calibration) => (raw, calibration.millivolts(raw), true),
+            None => (raw, raw as u32 * ADC_MAX_MILLIVOLTS / ADC_MAX_RAW, false),
+        })
+    }
+
+    // switches the pin to analog mode, which connects it to the touch sensor, and measures it once
+    pub fn read_touch_pin<'a>(&'a mut self, pin: u8, force: bool) -> Result<u32, RushError> {
+        if !TOUCH_PINS.contains(&pin) {
+            return Err(RushError::NoSuchPin(
+                "pin has no touch channel - only gpio.1 to gpio.14 can be touched",
+            ));
+        }
+        self.check_usable(pin, Some(TOUCH_OWNER), force)?;
+        self.release_pwm(pin);
+
+        let compound_pin = &mut self.pins[pin as usize];
+        compound_pin.pin.to_mode(RushPinMode::Analog);
+        compound_pin.last_known_state = None; // the digital level is unknown while the pin is analog
+        self.touch_sensor.read(pin)
+    }
+
+    pub fn write_pin<'a>(
+        &'a mut self,
+        pin: u8,
+        state: PinState,
+        force: bool,
+    ) -> Result<(), RushError> {
+        self.check_usable(pin, None, force)?;
+        self.release_pwm(pin);
+        self.get_pin(pin).to_output().set_state(state)?;
+        self.pins[pin as usize].last_known_state = Some(state == PinState::High);
+        Ok(())
+    }
+
+    pub fn set_pin_mode<'a>(
+        &'a mut self,
+        pin: u8,
+        mode: RushPinMode,
+        force: bool,
+    ) -> Result<(), RushError> {
+        self.check_usable(pin, None, force)?;
+        let any_pin = self.get_pin(pin);
+        match any_pin {
+            None => return Err(RushError::NoSuchPin("pin does not exist")),
+            Some(p) if mode == RushPinMode::Analog && !p.is_analog() => {
+                return Err(RushError::NoSuchPin("pin has no analog function"))
+            }
+            Some(_) => (),
+        }
+        self.release_pwm(pin);
+        let state = self.get_pin(pin).to_mode(mode).read_state().ok();
+        self.pins[pin as usize].last_known_state = state;
+        Ok(())
+    }
+
+    // switches the pin to a push pull output driven by a ledc channel - a pin that already runs pwm keeps its channel
+    pub fn start_pwm<'a>(
+        &'a mut self,
+        pin: u8,
+        frequency_hz: u32,
+        duty_pct: u8,
+        force: bool,
+    ) -> Result<(), RushError> {
+        if duty_pct > 100 {
+            return Err(RushError::InvalidValue(
+                "duty cycle must be between 0 and 100 percent",
+            ));
+        }
+        if self.get_pin(pin).is_none() {
+            return Err(RushError::NoSuchPin("pin does not exist"));
+        }
+        self.check_usable(pin, None, force)?;
+        // as many duty bits as the apb clock allows at this frequency - the ledc supports up to 14
+        let duty = match (1..=14u32)
+            .rev()
+            .find(|bits| (frequency_hz as u64) << bits <= PWM_CLOCK_HZ)
+        {
+            _ if frequency_hz == 0 => {
+                return Err(RushError::InvalidValue(
+                    "frequency must be greater than zero",
+                ))
+            }
+            None => {
+                return Err(RushError::InvalidValue(
+                    "frequency is too high - at most 40 MHz are possible",
+                ))
+            }
+            Some(bits) => duty_from_bits(bits),
+        };
+
+        // a pin that already runs pwm keeps its channel. channels and timers are only booked once the new setting
+        // runs, so a failure leaves the old one in place
+        let own_channel = self.pwm_channel(pin);
+        let own_timer = own_channel
+            .and_then(|channel_index| self.pwm_channels[channel_index])
+            .map(|channel| channel.timer);
+        let channel_index =
+            match own_channel.or_else(|| self.pwm_channels.iter().position(|c| c.is_none())) {
+                None => return Err(RushError::Busy("all 8 pwm channels are in use")),
+                Some(channel_index) => channel_index,
+            };
+        // a timer that already runs at the frequency is shared. otherwise a timer is retuned, but only one no other
+        // channel uses - a free one, or the one this pin has to itself
+        let timer_index = match self
+            .pwm_timers
+            .iter()
+            .position(|t| matches!(t, Some(t) if t.frequency_hz == frequency_hz))
+            .or_else(|| self.pwm_timers.iter().position(|t| t.is_none()))
+            .or_else(|| {
+                own_timer.filter(|&timer_index| {
+                    matches!(
+                        self.pwm_timers[timer_index],
+                        Some(PwmTimer { channels: 1, .. })
+                    )
+                })
+            }) {
+            Some(timer_index) => timer_index,
+            None => {
+                return Err(RushError::Busy("all 4 pwm timers are in use - at most 4 different frequencies can run at a time"));
+            }
+        };
+
+        // the ledc driver borrows the peripheral - it only lives for this block, the hardware keeps running afterwards
+        let result = {
+            let mut ledc = LEDC::new(
+                &mut self.ledc,
+                self.clocks,
+                &mut self.peripheral_clock_control,
+            );
+            ledc.set_global_slow_clock(LSGlobalClkSource::APBClk);
+            let mut timer = ledc.get_timer::<LowSpeed>(PWM_TIMERS[timer_index]);
+            // the hal only lets a channel use a timer it configured itself - a shared timer is given the divider and
+            // resolution it already runs at, which does not restart its counter or disturb the other channels
+            let timer_config = timer::config::Config {
+                duty,
+                clock_source: timer::LSClockSource::APBClk,
+                frequency: frequency_hz.Hz(),
+            };
+            match timer.configure(timer_config) {
+                Err(_) => Err(RushError::InvalidValue(
+                    "frequency is out of range for the pwm timer",
+                )),
+                Ok(()) => self.pins[pin as usize]
+                    .pin
+                    .to_mode(RushPinMode::PushPull)
+                    .start_pwm(&ledc, PWM_CHANNELS[channel_index], &timer, duty_pct),
+            }
+        };
+        result?;
+
+        if let Some(channel_index) = own_channel {
+            self.free_pwm_timer(channel_index);
+        }
+        self.pwm_channels[channel_index] = Some(PwmChannel {
+            pin,
+            timer: timer_index,
+        });
+        let channels = self.pwm_timers[timer_index].map_or(0, |t| t.channels);
+        self.pwm_timers[timer_index] = Some(PwmTimer {
+            frequency_hz,
+            channels: channels + 1,
+        });
+        self.pins[pin as usize].last_known_state = None;
+        Ok(())
+    }
+
+    // releases the channel and leaves the pin as a push pull output at low level
+    pub fn stop_pwm<'a>(&'a mut self, pin: u8) -> Result<(), RushError> {
+        if self.pwm_channel(pin).is_none() {
+            return Err(RushError::WrongMode("pin is not driven by pwm"));
+        }
+        // the pin passed the policy when its pwm was started
+        self.write_pin(pin, PinState::Low, true)
+    }
+
+    fn pwm_channel(&self, pin: u8) -> Option<usize> {
+        self.pwm_channels
+            .iter()
+            .position(|c| matches!(c, Some(c) if c.pin == pin))
+    }
+
+    fn free_pwm_timer(&mut self, channel_index: usize) {
+        if let Some(channel) = self.pwm_channels[channel_index] {
+            self.pwm_timers[channel.timer] = match self.pwm_timers[channel.timer] {
+                Some(PwmTimer { channels, .. }) if channels <= 1 => None,
+                Some(t) => Some(PwmTimer {
+                    frequency_hz: t.frequency_hz,
+                    channels: t.channels - 1,
+                }),
+                None => None,
+            };
+        }
+    }
+
+    // stops the channel and switches the pin to input, which detaches it - the next mode change connects the pin
+    // to its gpio output register again
+    fn release_pwm(&mut self, pin: u8) {
+        if let Some(channel_index) = self.pwm_channel(pin) {
+            stop_pwm_channel(channel_index);
+            self.free_pwm_timer(channel_index);
+            self.pwm_channels[channel_index] = None;
+            self.get_pin(pin).to_mode(RushPinMode::Floating);
+        }
+    }
+
+    pub fn i2c_open<'a>(
+        &'a mut self,
+        sda: u8,
+        scl: u8,
+        frequency_hz: u32,
+        force: bool,
+    ) -> Result<(), RushError> {
+        if sda == scl {
+            return Err(RushError::InvalidValue(
+                "sda and scl must be different pins",
+            ));
+        }
+        if frequency_hz == 0 || frequency_hz > 1_000_000 {
+            return Err(RushError::InvalidValue(
+                "frequency must be between 1 hz and 1 mhz",
+            ));
+        }
+        self.check_usable(sda, Some("i2c"), force)?;
+        self.check_usable(scl, Some("i2c"), force)?;
+        // reopening moves the bus - its old pins go back to being floating inputs
+        self.i2c_close().ok();
+
+        self.claim_pin(sda, "i2c");
+        self.claim_pin(scl, "i2c");
+        self.pins[sda as usize].pin.connect_peripheral(
+            Some(gpio::OutputSignal::I2CEXT0_SDA),
+            Some(gpio::InputSignal::I2CEXT0_SDA),
+            true,
+        );
+        self.pins[scl as usize].pin.connect_peripheral(
+            Some(gpio::OutputSignal::I2CEXT0_SCL),
+            Some(gpio::InputSignal::I2CEXT0_SCL),
+            true,
+        );
+        self.i2c_bus = Some(I2cBus {
+            sda,
+            scl,
+            frequency_hz,
+        });
+        Ok(())
+    }
+
+    pub fn i2c_close<'a>(&'a mut self) -> Result<(), RushError> {
+        match self.i2c_bus.take() {
+            None => Err(RushError::WrongMode("i2c bus is not open")),
+            Some(bus) => {
+                self.release_pin(bus.sda, Some(gpio::InputSignal::I2CEXT0_SDA));
+                self.release_pin(bus.scl, Some(gpio::InputSignal::I2CEXT0_SCL));
+                Ok(())
+            }
+        }
+    }
+
+    // writes `write` and then reads `read.len()` bytes - either of them may be empty
+    pub fn i2c_transfer<'a>(
+        &'a mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), RushError> {
+        if address > 0x7f {
+            return Err(RushError::InvalidValue("address must be a 7 bit address"));
+        }
+        let mut i2c = self.i2c_driver()?;
+        let result = match (write.is_empty(), read.is_empty()) {
+            (true, true) => Ok(()),
+            (false, true) => i2c.write(address, write),
+            (true, false) => i2c.read(address, read),
+            (false, false) => i2c.write_read(address, write, read),
+        };
+        result.map_err(i2c_error)
+    }
+
+    // every address that acknowledges a one byte read
+    pub fn i2c_scan<'a>(&'a mut self) -> Result<heapless::Vec<u8, 112>, RushError> {
+        let mut i2c = self.i2c_driver()?;
+        let mut found = heapless::Vec::new();
+        for address in 0x08..=0x77 {
+            match i2c.read(address, &mut [0u8; 1]) {
+                Ok(()) => {
+                    found.push(address).ok();
+                }
+                Err(esp32s3_hal::i2c::Error::AckCheckFailed) => (),
+                Err(err) => return Err(i2c_error(err)),
+            }
+        }
+        Ok(found)
+    }
+
+    // the driver only lives for one command - the pins were already routed by i2c_open().
+    // it borrows self.i2c0, so it cannot be kept in the bus state next to it. rebuilding it is cheap:
+    // I2C::new only configures the controller and its clock, it does not put anything on the bus
+    fn i2c_driver<'a>(&'a mut self) -> Result<I2C<'a, esp32s3_hal::peripherals::I2C0>, RushError> {
+        match self.i2c_bus {
+            None => Err(RushError::WrongMode(
+                "i2c bus is not open - use `i2c open` first",
+            )),
+            Some(bus) => Ok(I2C::new(
+                &mut self.i2c0,
+                RushRoutedPin(bus.sda),
+                RushRoutedPin(bus.scl),
+                bus.frequency_hz.Hz(),
+                &mut self.peripheral_clock_control,
+                self.clocks,
+            )),
+        }
+    }
+
+    // spi.2 and spi.3 are the general purpose spi controllers - spi.0 and spi.1 belong to the flash
+    pub fn spi_open<'a>(
+        &'a mut self,
+        bus: u8,
+        pins: [u8; 4], // sck, mosi, miso, cs
+        mode: SpiMode,
+        frequency_hz: u32,
+        force: bool,
+    ) -> Result<(), RushError> {
+        let (owner, signals) = spi_bus_signals(bus)?;
+        if frequency_hz == 0 || frequency_hz > 80_000_000 {
+            return Err(RushError::InvalidValue(
+                "frequency must be between 1 hz and 80 mhz",
+            ));
+        }
+        for (index, pin) in pins.iter().enumerate() {
+            if pins[..index].contains(pin) {
+                return Err(RushError::InvalidValue(
+                    "sck, mosi, miso and cs must be different pins",
+                ));
+            }
+            self.check_usable(*pin, Some(owner), force)?;
+        }
+        self.spi_close(bus).ok();
+
+        let [sck, mosi, miso, cs] = pins;
+        for pin in pins {
+            self.claim_pin(pin, owner);
+        }
+        self.pins[sck as usize]
+            .pin
+            .connect_peripheral(Some(signals.sck), None, false);
+        self.pins[mosi as usize]
+            .pin
+            .connect_peripheral(Some(signals.mosi), None, false);
+        self.pins[miso as usize]
+            .pin
+            .connect_peripheral(None, Some(signals.miso), false);
+        self.pins[cs as usize]
+            .pin
+            .connect_peripheral(Some(signals.cs), None, false);
+        self.spi_buses[bus as usize - 2] = Some(SpiBus {
+            sck,
+            mosi,
+            miso,
+            cs,
+            mode,
+            frequency_hz,
+        });
+        Ok(())
+    }
+
+    pub fn spi_close<'a>(&'a mut self, bus: u8) -> Result<(), RushError> {
+        let (_, signals) = spi_bus_signals(bus)?;
+        match self.spi_buses[bus as usize - 2].take() {
+            None => Err(RushError::WrongMode("spi bus is not open")),
+            Some(spi_bus) => {
+                self.release_pin(spi_bus.sck, None);
+                self.release_pin(spi_bus.mosi, None);
+                self.release_pin(spi_bus.miso, Some(signals.miso));
+                self.release_pin(spi_bus.cs, None);
+                Ok(())
+            }
+        }
+    }
+
+    // full duplex - every byte in `bytes` is replaced by the byte received while sending it
+    pub fn spi_transfer<'a>(&'a mut self, bus: u8, bytes: &mut [u8]) -> Result<(), RushError> {
+        spi_bus_signals(bus)?;
+        let spi_bus = match self.spi_buses[bus as usize - 2] {
+            None => {
+                return Err(RushError::WrongMode(
+                    "spi bus is not open - use `spi open` first",
+                ))
+            }
+            Some(spi_bus) => spi_bus,
+        };
+
+        // like i2c, the driver only lives for one command - the pins were already routed by spi_open()
+        let result = match bus {
+            2 => Spi::new(
+                &mut self.spi2,
+                RushRoutedPin(spi_bus.sck),
+                RushRoutedPin(spi_bus.mosi),
+                RushRoutedPin(spi_bus.miso),
+                RushRoutedPin(spi_bus.cs),
+                spi_bus.frequency_hz.Hz(),
+                spi_bus.mode,
+                &mut self.peripheral_clock_control,
+                self.clocks,
+            )
+            .transfer(bytes)
+            .map(|_| ()),
+            _ => Spi::new(
+                &mut self.spi3,
+                RushRoutedPin(spi_bus.sck),
+                RushRoutedPin(spi_bus.mosi),
+                RushRoutedPin(spi_bus.miso),
+                RushRoutedPin(spi_bus.cs),
+                spi_bus.frequency_hz.Hz(),
+                spi_bus.mode,
+                &mut self.peripheral_clock_control,
+                self.clocks,
+            )
+            .transfer(bytes)
+            .map(|_| ()),
+        };
+        result.map_err(|_| RushError::Hardware("esp_hal_common::spi::Spi.transfer() failed"))
+    }
+
+    // uart0 carries the log output, so the bridge uses uart1
+    pub fn uart_open<'a>(
+        &'a mut self,
+        client_id: u8,
+        tx: u8,
+        rx: u8,
+        baud_rate: u32,
+        force: bool,
+    ) -> Result<(), RushError> {
+        if tx == rx {
+            return Err(RushError::InvalidValue("tx and rx must be different pins"));
+        }
+        if baud_rate < 300 || baud_rate > 5_000_000 {
+            return Err(RushError::InvalidValue(
+                "baud rate must be between 300 and 5000000",
+            ));
+        }
+        // the other client would only find out when its next read fails - it has to end its bridge first
+        if let Some(UartBus {
+            bridged_by: Some(other),
+            ..
+        }) = self.uart_bus
+        {
+            if other != client_id {
+                return Err(RushError::Busy("uart is bridged to another client"));
+            }
+        }
+        self.check_usable(tx, Some("uart"), force)?;
+        self.check_usable(rx, Some("uart"), force)?;
+        self.uart_close().ok();
+
+        self.claim_pin(tx, "uart");
+        self.claim_pin(rx, "uart");
+        self.pins[tx as usize]
+            .pin
+            .connect_peripheral(Some(gpio::OutputSignal::U1TXD), None, false);
+        self.pins[rx as usize]
+            .pin
+            .connect_peripheral(None, Some(gpio::InputSignal::U1RXD), false);
+        self.uart_bus = Some(UartBus {
+            tx,
+            rx,
+            bridged_by: None,
+        });
+
+        // only this driver configures the uart - later ones keep its settings and the bytes in its fifo
+        self.uart_driver(Some(Config {
+            baudrate: baud_rate,
+            ..Default::default()
+        }))?;
+        Ok(())
+    }
+
+    pub fn uart_close<'a>(&'a mut self) -> Result<(), RushError> {
+        match self.uart_bus.take() {
+            None => Err(RushError::WrongMode("uart is not open")),
+            Some(bus) => {
+                self.release_pin(bus.tx, None);
+                self.release_pin(bus.rx, Some(gpio::InputSignal::U1RXD));
+                Ok(())
+            }
+        }
+    }
+
+    pub fn uart_start_bridge<'a>(&'a mut self, client_id: u8) -> Result<(), RushError> {
+        match &mut self.uart_bus {
+            None => Err(RushError::WrongMode(
+                "uart is not open - use `uart open` first",
+            )),
+            Some(UartBus {
+                bridged_by: Some(other),
+                ..
+            }) if *other != client_id => {
+                Err(RushError::Busy("uart is already bridged to another client"))
+            }
+            Some(bus) => {
+                bus.bridged_by = Some(client_id);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn uart_stop_bridge(&mut self, client_id: u8) {
+        if let Some(bus) = &mut self.uart_bus {
+            if bus.bridged_by == Some(client_id) {
+                bus.bridged_by = None;
+            }
+        }
+    }
+
+    pub fn uart_write<'a>(&'a mut self, client_id: u8, bytes: &[u8]) -> Result<(), RushError> {
+        self.check_bridged_by(client_id)?;
+        match self.uart_driver(None)?.write_bytes(bytes) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(RushError::Hardware(
+                "esp_hal_common::uart::Uart.write_bytes() failed",
+            )),
+        }
+    }
+
+    // everything that is waiting in the rx fifo - at most bytes.len()
+    pub fn uart_read<'a>(
+        &'a mut self,
+        client_id: u8,
+        bytes: &mut [u8],
+    ) -> Result<usize, RushError> {
+        self.check_bridged_by(client_id)?;
+        let mut uart = self.uart_driver(None)?;
+        let mut len = 0;
+        while len < bytes.len() {
+            match esp32s3_hal::ehal::serial::Read::read(&mut uart) {
+                Ok(byte) => {
+                    bytes[len] = byte;
+                    len += 1;
+                }
+                Err(nb::Error::WouldBlock) => break,
+                Err(nb::Error::Other(_)) => {
+                    return Err(RushError::Hardware(
+                        "embedded_hal::serial::Read.read() failed",
+                    ))
+                }
+            }
+        }
+        Ok(len)
+    }
+
+    fn check_bridged_by(&self, client_id: u8) -> Result<(), RushError> {
+        match self.uart_bus {
+            Some(UartBus {
+                bridged_by: Some(id),
+                ..
+            }) if id == client_id => Ok(()),
+            Some(_) => Err(RushError::WrongMode("uart is not bridged to this client")),
+            None => Err(RushError::WrongMode("uart was closed")),
+        }
+    }
+
+    // the pins were already routed by uart_open(), so the driver does not get any
+    fn uart_driver<'a>(
+        &'a mut self,
+        config: Option<Config>,
+    ) -> Result<Uart<'a, esp32s3_hal::peripherals::UART1>, RushError> {
+        match self.uart_bus {
+            None => Err(RushError::WrongMode(
+                "uart is not open - use `uart open` first",
+            )),
+            Some(_) => Ok(Uart::new_with_config(
+                &mut self.uart1,
+                config,
+                None::<TxRxPins<RushRoutedPin, RushRoutedPin>>,
+                self.clocks,
+                &mut self.peripheral_clock_control,
+            )),
+        }
+    }
+
+    // counts rising edges from now on - the pin is an input without pull resistor while it is counted
+    pub fn count_start<'a>(&'a mut self, pin: u8, force: bool) -> Result<(), RushError> {
+        if self.pcnt_unit(pin).is_some() {
+            return Err(RushError::Busy("pin is already counted"));
+        }
+        let unit_index = match self.pcnt_units.iter().position(|unit| unit.is_none()) {
+            None => {
+                return Err(RushError::Busy(
+                    "all 4 pulse counters are in use - stop counting another pin first",
+                ))
+            }
+            Some(unit_index) => unit_index,
+        };
+        self.check_usable(pin, Some("count"), force)?;
+
+        PCNT_DRIVER.lock(|pcnt| -> Result<(), RushError> {
+            let pcnt = pcnt.borrow();
+            let pcnt = match &*pcnt {
+                None => return Err(RushError::Hardware("pulse counter is not available")),
+                Some(pcnt) => pcnt,
+            };
+            let mut unit = pcnt.get_unit(PCNT_UNITS[unit_index]);
+            if unit
+                .configure(unit::Config {
+                    low_limit: 0,
+                    high_limit: PCNT_HIGH_LIMIT,
+                    ..Default::default()
+                })
+                .is_err()
+            {
+                return Err(RushError::Hardware(
+                    "esp_hal_common::pcnt::unit::Unit.configure() failed",
+                ));
+            }
+            // the sources only matter for routing, which is done below with the real pin
+            unit.get_channel(esp32s3_hal::pcnt::channel::Number::Channel0)
+                .configure(
+                    PcntSource::always_high(),
+                    PcntSource::always_high(),
+                    esp32s3_hal::pcnt::channel::Config {
+                        lctrl_mode: CtrlMode::Keep,
+                        hctrl_mode: CtrlMode::Keep,
+                        pos_edge: EdgeMode::Increment,
+                        neg_edge: EdgeMode::Hold,
+                        invert_ctrl: false,
+                        invert_sig: false,
+                    },
+                );
+            unit.events(unit::Events {
+                high_limit: true,
+                ..Default::default()
+            });
+            unit.listen();
+            unit.clear();
+            PCNT_OVERFLOWS[unit_index].store(0, Ordering::SeqCst);
+            unit.resume();
+            Ok(())
+        })?;
+
+        self.claim_pin(pin, "count");
+        self.pins[pin as usize]
+            .pin
+            .connect_peripheral(None, Some(pcnt_signal(unit_index)), false);
+        self.pcnt_units[unit_index] = Some(pin);
+        Ok(())
+    }
+
+    pub fn count_stop<'a>(&'a mut self, pin: u8) -> Result<(), RushError> {
+        let unit_index = self.pcnt_unit(pin).ok_or(RushError::WrongMode(
+            "pin is not counted - use `count gpio.N start` first",
+        ))?;
+        PCNT_DRIVER.lock(|pcnt| {
+            if let Some(pcnt) = &*pcnt.borrow() {
+                let mut unit = pcnt.get_unit(PCNT_UNITS[unit_index]);
+                unit.pause();
+                unit.events(unit::Events::default());
+            }
+        });
+        self.release_pin(pin, Some(pcnt_signal(unit_index)));
+        self.pcnt_units[unit_index] = None;
+        Ok(())
+    }
+
+    // rising edges since count_start() or the last count_reset() - the count stops at u32::MAX overflows,
+    // about 1.3e14 edges, which take more than a month even at the fastest rate the counter can follow
+    pub fn count_read<'a>(&'a mut self, pin: u8) -> Result<u64, RushError> {
+        let unit_index = self.pcnt_unit(pin).ok_or(RushError::WrongMode(
+            "pin is not counted - use `count gpio.N start` first",
+        ))?;
+        Ok(PCNT_DRIVER.lock(|pcnt| match &*pcnt.borrow() {
+            None => 0,
+            Some(pcnt) => {
+                let unit = pcnt.get_unit(PCNT_UNITS[unit_index]);
+                // an overflow between the two reads would be counted twice or not at all - so read until both agree
+                loop {
+                    let overflows = PCNT_OVERFLOWS[unit_index].load(Ordering::SeqCst);
+                    let value = unit.get_value();
+                    if overflows == PCNT_OVERFLOWS[unit_index].load(Ordering::SeqCst) {
+                        break overflows as u64 * PCNT_HIGH_LIMIT as u64 + value as u64;
+                    }
+                }
+            }
+        }))
+    }
+
+    pub fn count_reset<'a>(&'a mut self, pin: u8) -> Result<(), RushError> {
+        let unit_index = self.pcnt_unit(pin).ok_or(RushError::WrongMode(
+            "pin is not counted - use `count gpio.N start` first",
+        ))?;
+        PCNT_DRIVER.lock(|pcnt| {
+            if let Some(pcnt) = &*pcnt.borrow() {
+                pcnt.get_unit(PCNT_UNITS[unit_index]).clear();
+                PCNT_OVERFLOWS[unit_index].store(0, Ordering::SeqCst);
+            }
+        });
+        Ok(())
+    }
+
+    pub fn is_counted(&self, pin: u8) -> bool {
+        self.pcnt_unit(pin).is_some()
+    }
+
+    fn pcnt_unit(&self, pin: u8) -> Option<usize> {
+        self.pcnt_units.iter().position(|unit| *unit == Some(pin))
+    }
+
+    pub fn board(&self) -> &'static RushBoard {
+        self.board
+    }
+
+    // every claim or reconfiguration of a pin passes here - forbidden pins are never used, pins that only warn
+    // are used if the client insists with force. a pin of a bus or counter is only handed back to its owner,
+    // which is about to reopen it - without an owner the caller wants the pin as a plain gpio. a pad that is
+    // watched for touches only serves further touch reads, anything else would take it out of touch mode
+    fn check_usable(&self, pin: u8, owner: Option<&str>, force: bool) -> Result<(), RushError> {
+        match self.board.pin_policy(pin) {
+            (RushPinPolicy::Forbidden, reason) => return Err(RushError::Forbidden(reason)),
+            (RushPinPolicy::Warn, reason) if !force => return Err(RushError::Forbidden(reason)),
+            _ => (),
+        }
+        match (self.pins.get(pin as usize), owner) {
+            (
+                Some(PinManagerCompoundPin {
+                    touch_threshold: Some(_),
+                    ..
+                }),
+                owner,
+            ) if owner != Some(TOUCH_OWNER) => Err(RushError::WrongMode(
+                "pin is watched as a touch pad - unwatch touch.N first",
+            )),
+            (None | Some(PinManagerCompoundPin { pin: None, .. }), Some(_)) => {
+                Err(RushError::NoSuchPin("pin does not exist"))
+            }
+            (
+                Some(PinManagerCompoundPin {
+                    claimed_by: Some(_),
+                    ..
+                }),
+                None,
+            ) => Err(RushError::Busy(
+                "pin is used by a bus or a counter - `list` shows which one",
+            )),
+            (
+                Some(PinManagerCompoundPin {
+                    claimed_by: Some(claimed_by),
+                    ..
+                }),
+                Some(owner),
+            ) if *claimed_by != owner => Err(RushError::Busy(
+                "pin is already used by another bus or counter",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // hands the pin over to a bus or counter - watchers are told, pwm is stopped
+    fn claim_pin(&mut self, pin: u8, owner: &'static str) {
+        self.release_pwm(pin);
+        let compound_pin = &mut self.pins[pin as usize];
+        if compound_pin.last_state_if_watched.is_some() {
+            let reason = RushError::Busy("pin is now used by a bus or a counter");
+            PIN_EVENTS.immediate_publisher().publish_immediate(
+                match compound_pin.touch_threshold {
+                    Some(_) => RushPinEvent::TouchStopped { pin, reason },
+                    None => RushPinEvent::Stopped { pin, reason },
+                },
+            );
+        }
+        compound_pin.last_state_if_watched = None;
+        compound_pin.touch_threshold = None;
+        compound_pin.watchers = 0;
+        compound_pin.last_known_state = None;
+        compound_pin.claimed_by = Some(owner);
+    }
+
+    // detaches the bus signals and leaves the pin as a floating input
+    fn release_pin(&mut self, pin: u8, input_signal: Option<gpio::InputSignal>) {
+        let compound_pin = &mut self.pins[pin as usize];
+        compound_pin.pin.disconnect_peripheral(input_signal);
+        compound_pin.pin.to_mode(RushPinMode::Floating);
+        compound_pin.claimed_by = None;
+    }
+
+    pub fn watch_pin<'a>(
+        &'a mut self,
+        pin: u8,
+        client_id: u8,
+        force: bool,
+    ) -> Result<bool, RushError> {
+        let current_state = self.read_pin(pin, true, force)?;
+        let pin = &mut self.pins[pin as usize];
+        pin.watchers |= 1 << client_id;
+        if pin.last_state_if_watched.is_none() {
+            // keep the state of a pin that is already watched, otherwise its other watchers could miss an edge
+            pin.last_state_if_watched = Some(current_state);
+        }
+        Ok(current_state)
+    }
+
+    // the threshold is shared by all watchers of the pad - watching it with a new threshold replaces the old one
+    pub fn watch_touch_pin<'a>(
+        &'a mut self,
+        pin: u8,
+        client_id: u8,
+        threshold: u32,
+        force: bool,
+    ) -> Result<(u32, bool), RushError> {
+        if matches!(
+            self.pins.get(pin as usize),
+            Some(PinManagerCompoundPin {
+                last_state_if_watched: Some(_),
+                touch_threshold: None,
+                ..
+            })
+        ) {
+            return Err(RushError::WrongMode(
+                "pin is watched as a gpio - unwatch gpio.N first",
+            ));
+        }
+        let value = self.read_touch_pin(pin, force)?;
+        let touched = value > threshold;
+        let pin = &mut self.pins[pin as usize];
+        pin.watchers |= 1 << client_id;
+        pin.touch_threshold = Some(threshold);
+        pin.last_state_if_watched = Some(touched);
+        Ok((value, touched))
+    }
+
+    pub fn unwatch_pin<'a>(&'a mut self, pin: u8, client_id: u8) -> Result<(), RushError> {
         match self.get_pin(pin) {
-            None => Err("pin does not exist"),
+            None => Err(RushError::NoSuchPin("pin does not exist")),
             Some(_) => {
-                self.pins[pin as usize].last_state_if_watched = None;
+                let pin = &mut self.pins[pin as usize];
+                pin.watchers &= !(1 << client_id);
+                if pin.watchers == 0 {
+                    pin.last_state_if_watched = None;
+                    pin.touch_threshold = None;
+                }
                 Ok(())
             }
         }
     }
 
-    pub async fn poll_watched_pins<'a, 'b>(&'a mut self, fmt_buffer: &'b mut [u8]) -> &'b str {
+    pub fn unwatch_all_pins(&mut self, client_id: u8) {
+        for pin_num in 0..self.pins.len() as u8 {
+            self.unwatch_pin(pin_num, client_id).ok();
+        }
+    }
+
+    pub fn list_pins<'a, 'b>(&'a mut self, fmt_buffer: &'b mut [u8]) -> &'b str {
+        let mut len = fmt_truncate(
+            fmt_buffer,
+            format_args!(
+                "{:<8}{:<10}{:<8}{:<6}{}\n",
+                "pin", "mode", "type", "level", "watched"
+            ),
+        )
+        .len();
+
+        let pwm_channels = self.pwm_channels;
+        for (pin_num, pin) in self.pins.iter_mut().enumerate() {
+            let any_pin = match &pin.pin {
+                None => continue, // pins 22, 23, 24 and 25 just don't exist
+                Some(any_pin) => any_pin,
+            };
+            let is_pwm = pwm_channels
+                .iter()
+                .any(|c| matches!(c, Some(c) if c.pin as usize == pin_num));
+
+            // reading does not change the mode of inputs and outputs, so the level can be refreshed here
+            let mode = any_pin.mode();
+            if mode != RushPinMode::Unknown && !is_pwm && pin.claimed_by.is_none() {
+                if let Ok(state) = any_pin.read_state() {
+                    pin.last_known_state = Some(state);
+                }
+            }
+
+            len += fmt_truncate(
+                &mut fmt_buffer[len..],
+                format_args!(
+                    "gpio.{:<3}{:<10}{:<8}{:<6}{}\n",
+                    pin_num,
+                    match pin.claimed_by {
+                        Some(owner) => &owner as &dyn fmt::Display,
+                        None if is_pwm => &"pwm",
+                        None if pin.touch_threshold.is_some() => &TOUCH_OWNER,
+                        None => &mode,
+                    },
+                    if any_pin.is_analog() {
+                        "analog"
+                    } else {
+                        "digital"
+                    },
+                    match pin.last_known_state {
+                        None => "-",
+                        Some(false) => "0",
+                        Some(true) => "1",
+                    },
+                    if pin.last_state_if_watched.is_some() {
+                        "yes"
+                    } else {
+                        "no"
+                    },
+                ),
+            )
+            .len();
+        }
+
+        from_utf8(&fmt_buffer[..len]).unwrap_or_default()
+    }
+
+    pub async fn wait_for_pin_event(&mut self) -> RushPinEvent {
         loop {
-            let pin_num = self.next_pin_to_poll;
-            self.next_pin_to_poll += 1;
-            self.next_pin_to_poll %= self.pins.len() as u8;
+            // check every pin once - starting after the pin that reported last, so a busy pin cannot starve the others
+            for _ in 0..self.pins.len() {
+                let pin_num = self.next_pin_to_poll;
+                self.next_pin_to_poll += 1;
+                self.next_pin_to_poll %= self.pins.len() as u8;
+
+                let pin = &mut self.pins[pin_num as usize];
+                if let (Some(laststate), Some(threshold)) =
+                    (pin.last_state_if_watched, pin.touch_threshold)
+                {
+                    match self.touch_sensor.read(pin_num) {
+                        Err(reason) => {
+                            pin.last_state_if_watched = None;
+                            pin.touch_threshold = None;
+                            pin.watchers = 0;
+                            return RushPinEvent::TouchStopped {
+                                pin: pin_num,
+                                reason,
+                            };
+                        }
+                        Ok(value) => {
+                            let touched = value > threshold;
+                            if touched != laststate {
+                                pin.last_state_if_watched = Some(touched);
+                                return RushPinEvent::Touched {
+                                    pin: pin_num,
+                                    value,
+                                    touched,
+                                };
+                            }
+                        }
+                    };
+                } else if let Some(laststate) = pin.last_state_if_watched {
+                    match pin.pin.read_state() {
+                        Err(reason) => {
+                            pin.last_state_if_watched = None;
+                            pin.watchers = 0;
+                            return RushPinEvent::Stopped {
+                                pin: pin_num,
+                                reason,
+                            };
+                        }
+                        Ok(state) => {
+                            if state != laststate {
+                                pin.last_state_if_watched = Some(state);
+                                pin.last_known_state = Some(state);
+                                return RushPinEvent::Changed {
+                                    pin: pin_num,
+                                    state,
+                                };
+                            }
+                        }
+                    };
+                }
+            }
+
+            // nothing to report - sleep until a watched pin sees an edge, or watched touch pads are due again
+            let touch_due = self
+                .pins
+                .iter()
+                .any(|pin| pin.last_state_if_watched.is_some() && pin.touch_threshold.is_some());
+            let touch_timer = async {
+                match touch_due {
+                    true => Timer::after(TOUCH_POLL_INTERVAL).await,
+                    false => pending().await,
+                }
+            };
+            // one waiter per pin, so they fit a fixed array that select_array keeps pinned while it polls them
+            let mut pins = self.pins.iter_mut();
+            let edge_waiters: [_; 49] =
+                core::array::from_fn(|_| wait_for_watched_edge(pins.next()));
+            let edge = select(touch_timer, select_array(edge_waiters)).await;
+            let (pin_num, edge_result) = match edge {
+                Either::First(()) => continue, // measure the touch pads in the next sweep
+                Either::Second((edge_result, pin_num)) => (pin_num as u8, edge_result),
+            };
 
             let pin = &mut self.pins[pin_num as usize];
-            if let Some(laststate) = pin.last_state_if_watched {
-                match pin.pin.read_state() {
-                    Err(e) => {
-                        pin.last_state_if_watched = None;
-                        return fmt_truncate(
-                            fmt_buffer,
-                            format_args!("{}\n  => stopped watching gpio.{}\n", e, pin_num),
-                        );
-                    }
-                    Ok(state) => {
-                        if state != laststate {
-                            pin.last_state_if_watched = Some(state);
-                            return fmt_truncate(
-                                fmt_buffer,
-                                format_args!("gpio.{} = {}\n", pin_num, state as u8),
-                            );
+            match edge_result {
+                Err(reason) => {
+                    pin.last_state_if_watched = None;
+                    pin.watchers = 0;
+                    return RushPinEvent::Stopped {
+                        pin: pin_num,
+                        reason,
+                    };
+                }
+                Ok(()) => {
+                    // usually the next sweep reports the new level - but if the pin is already back
+                    // at its old level, the pulse was too short to be read and both edges are reported
+                    if let (Some(laststate), Ok(state)) =
+                        (pin.last_state_if_watched, pin.pin.read_state())
+                    {
+                        if state == laststate {
+                            pin.last_known_state = Some(state);
+                            return RushPinEvent::Pulse {
+                                pin: pin_num,
+                                state,
+                            };
                         }
                     }
-                };
-            }
+                }
+            };
+        }
+    }
+}
+
+// only pins watched as gpio ever wake up - every other pin waits forever
+async fn wait_for_watched_edge(pin: Option<&mut PinManagerCompoundPin>) -> Result<(), RushError> {
+    match pin {
+        Some(PinManagerCompoundPin {
+            last_state_if_watched: Some(_),
+            touch_threshold: None,
+            pin: Some(any_pin),
+            ..
+        }) => any_pin.wait_for_any_edge().await,
+        _ => pending().await,
+    }
+}
+
+// the only way for clients to get hold of the pin manager - makes watch_pins() release it first
+pub async fn lock_pin_manager(
+    pin_manager: &'static RushPinManagerMutex,
+) -> MutexGuard<'static, CriticalSectionRawMutex, RushPinManager> {
+    LOCK_WAITERS.fetch_add(1, Ordering::SeqCst);
+    LOCK_REQUESTED.signal(());
+    let guard = pin_manager.lock().await;
+    LOCK_WAITERS.fetch_sub(1, Ordering::SeqCst);
+    guard
+}
 
-            if pin_num == 0 {
-                Timer::after(Duration::from_millis(100)).await;
+#[embassy_executor::task]
+pub async fn watch_pins(pin_manager: &'static RushPinManagerMutex) -> ! {
+    let publisher = PIN_EVENTS.immediate_publisher();
+    loop {
+        {
+            let mut pin_manager = pin_manager.lock().await;
+            LOCK_REQUESTED.reset();
+            if LOCK_WAITERS.load(Ordering::SeqCst) == 0 {
+                if let Either::First(event) =
+                    select(pin_manager.wait_for_pin_event(), LOCK_REQUESTED.wait()).await
+                {
+                    publisher.publish_immediate(event);
+                }
             }
         }
+        // let the client that asked for the pin manager take it before locking it again
+        yield_now().await;
     }
 }
 
 pub trait RushPinOperations {
+    fn mode(&self) -> Option<RushPinMode>;
+    fn to_mode(&mut self, mode: RushPinMode) -> &mut Self;
     fn to_input(&mut self) -> &mut Self;
     fn to_output(&mut self) -> &mut Self;
-    fn read_state<'a, 'b>(&'a mut self) -> Result<bool, &'b str>;
-    fn set_state<'a, 'b>(&'a mut self, state: PinState) -> Result<(), &'b str>;
+    fn read_state<'a>(&'a mut self) -> Result<bool, RushError>;
+    fn set_state<'a>(&'a mut self, state: PinState) -> Result<(), RushError>;
+    fn start_pwm<'a>(
+        &'a mut self,
+        ledc: &'a LEDC<'a>,
+        channel: channel::Number,
+        timer: &'a timer::Timer<'a, LowSpeed>,
+        duty_pct: u8,
+    ) -> Result<(), RushError>;
+    fn connect_peripheral(
+        &mut self,
+        output: Option<gpio::OutputSignal>,
+        input: Option<gpio::InputSignal>,
+        open_drain: bool,
+    );
+    fn disconnect_peripheral(&mut self, input: Option<gpio::InputSignal>);
 }
 
 impl RushPinOperations for Option<RushAnyPin> {
+    fn mode(&self) -> Option<RushPinMode> {
+        self.as_ref().map(|pin| pin.mode())
+    }
+    fn to_mode(&mut self, mode: RushPinMode) -> &mut Self {
+        match self.take() {
+            None => (),
+            Some(pin) => {
+                self.replace(pin.to_mode(mode));
+            }
+        };
+        self
+    }
     fn to_input(&mut self) -> &mut Self {
         match self.take() {
             None => (),
@@ -158,18 +1468,45 @@ impl RushPinOperations for Option<RushAnyPin> {
         };
         self
     }
-    fn read_state<'a, 'b>(&'a mut self) -> Result<bool, &'b str> {
+    fn read_state<'a>(&'a mut self) -> Result<bool, RushError> {
         match self {
-            None => Err("pin does not exist"),
+            None => Err(RushError::NoSuchPin("pin does not exist")),
             Some(pin) => Ok(pin.read_state()?),
         }
     }
-    fn set_state<'a, 'b>(&'a mut self, state: PinState) -> Result<(), &'b str> {
+    fn set_state<'a>(&'a mut self, state: PinState) -> Result<(), RushError> {
         match self {
-            None => Err("pin does not exist"),
+            None => Err(RushError::NoSuchPin("pin does not exist")),
             Some(pin) => Ok(pin.set_state(state)?),
         }
     }
+    fn start_pwm<'a>(
+        &'a mut self,
+        ledc: &'a LEDC<'a>,
+        channel: channel::Number,
+        timer: &'a timer::Timer<'a, LowSpeed>,
+        duty_pct: u8,
+    ) -> Result<(), RushError> {
+        match self {
+            None => Err(RushError::NoSuchPin("pin does not exist")),
+            Some(pin) => Ok(pin.start_pwm(ledc, channel, timer, duty_pct)?),
+        }
+    }
+    fn connect_peripheral(
+        &mut self,
+        output: Option<gpio::OutputSignal>,
+        input: Option<gpio::InputSignal>,
+        open_drain: bool,
+    ) {
+        if let Some(pin) = self {
+            pin.connect_peripheral(output, input, open_drain);
+        }
+    }
+    fn disconnect_peripheral(&mut self, input: Option<gpio::InputSignal>) {
+        if let Some(pin) = self {
+            pin.disconnect_peripheral(input);
+        }
+    }
 }
 
 #[rustfmt::skip]
@@ -223,6 +1560,112 @@ pub enum RushAnyPin {
     Pin48(RushSinglePin<gpio::Bank1GpioRegisterAccess, gpio::SingleCoreInteruptStatusRegisterAccessBank1, gpio::Gpio48Signals, 48>),
 }
 
+// enum_dispatch can not forward async functions, so this one is dispatched by hand
+impl RushAnyPin {
+    #[rustfmt::skip]
+    pub async fn wait_for_any_edge<'a>(&'a mut self) -> Result<(), RushError> {
+        match self {
+            Self::Pin0 (p) => p.wait_for_any_edge().await,
+            Self::Pin1 (p) => p.wait_for_any_edge().await,
+            Self::Pin2 (p) => p.wait_for_any_edge().await,
+            Self::Pin3 (p) => p.wait_for_any_edge().await,
+            Self::Pin4 (p) => p.wait_for_any_edge().await,
+            Self::Pin5 (p) => p.wait_for_any_edge().await,
+            Self::Pin6 (p) => p.wait_for_any_edge().await,
+            Self::Pin7 (p) => p.wait_for_any_edge().await,
+            Self::Pin8 (p) => p.wait_for_any_edge().await,
+            Self::Pin9 (p) => p.wait_for_any_edge().await,
+            Self::Pin10(p) => p.wait_for_any_edge().await,
+            Self::Pin11(p) => p.wait_for_any_edge().await,
+            Self::Pin12(p) => p.wait_for_any_edge().await,
+            Self::Pin13(p) => p.wait_for_any_edge().await,
+            Self::Pin14(p) => p.wait_for_any_edge().await,
+            Self::Pin15(p) => p.wait_for_any_edge().await,
+            Self::Pin16(p) => p.wait_for_any_edge().await,
+            Self::Pin17(p) => p.wait_for_any_edge().await,
+            Self::Pin18(p) => p.wait_for_any_edge().await,
+            Self::Pin19(p) => p.wait_for_any_edge().await,
+            Self::Pin20(p) => p.wait_for_any_edge().await,
+            Self::Pin21(p) => p.wait_for_any_edge().await,
+            Self::Pin26(p) => p.wait_for_any_edge().await,
+            Self::Pin27(p) => p.wait_for_any_edge().await,
+            Self::Pin28(p) => p.wait_for_any_edge().await,
+            Self::Pin29(p) => p.wait_for_any_edge().await,
+            Self::Pin30(p) => p.wait_for_any_edge().await,
+            Self::Pin31(p) => p.wait_for_any_edge().await,
+            Self::Pin32(p) => p.wait_for_any_edge().await,
+            Self::Pin33(p) => p.wait_for_any_edge().await,
+            Self::Pin34(p) => p.wait_for_any_edge().await,
+            Self::Pin35(p) => p.wait_for_any_edge().await,
+            Self::Pin36(p) => p.wait_for_any_edge().await,
+            Self::Pin37(p) => p.wait_for_any_edge().await,
+            Self::Pin38(p) => p.wait_for_any_edge().await,
+            Self::Pin39(p) => p.wait_for_any_edge().await,
+            Self::Pin40(p) => p.wait_for_any_edge().await,
+            Self::Pin41(p) => p.wait_for_any_edge().await,
+            Self::Pin42(p) => p.wait_for_any_edge().await,
+            Self::Pin43(p) => p.wait_for_any_edge().await,
+            Self::Pin44(p) => p.wait_for_any_edge().await,
+            Self::Pin45(p) => p.wait_for_any_edge().await,
+            Self::Pin46(p) => p.wait_for_any_edge().await,
+            Self::Pin47(p) => p.wait_for_any_edge().await,
+            Self::Pin48(p) => p.wait_for_any_edge().await,
+        }
+    }
+
+    // only gpio 1 to 10 are connected to adc1 - the pin has to be in analog mode already
+    #[rustfmt::skip]
+    fn read_adc1(self, adc1: &mut ADC1) -> (Self, Result<u16, RushError>) {
+        match self {
+            Self::Pin1 (RushSinglePin::AdcAnalogPin(p)) => read_adc1(adc1, p),
+            Self::Pin2 (RushSinglePin::AdcAnalogPin(p)) => read_adc1(adc1, p),
+            Self::Pin3 (RushSinglePin::AdcAnalogPin(p)) => read_adc1(adc1, p),
+            Self::Pin4 (RushSinglePin::AdcAnalogPin(p)) => read_adc1(adc1, p),
+            Self::Pin5 (RushSinglePin::AdcAnalogPin(p)) => read_adc1(adc1, p),
+            Self::Pin6 (RushSinglePin::AdcAnalogPin(p)) => read_adc1(adc1, p),
+            Self::Pin7 (RushSinglePin::AdcAnalogPin(p)) => read_adc1(adc1, p),
+            Self::Pin8 (RushSinglePin::AdcAnalogPin(p)) => read_adc1(adc1, p),
+            Self::Pin9 (RushSinglePin::AdcAnalogPin(p)) => read_adc1(adc1, p),
+            Self::Pin10(RushSinglePin::AdcAnalogPin(p)) => read_adc1(adc1, p),
+            other => (other, Err(RushError::WrongMode("read_adc1() was called on a pin that is not an analog adc1 pin"))),
+        }
+    }
+}
+
+// the adc driver takes the pin by value and hands it back afterwards - the adc itself only lives for one sample
+fn read_adc1<RA, IRA, SIG, const GPIONUM: u8>(
+    adc1: &mut ADC1,
+    pin: gpio::GpioPin<gpio::Analog, RA, IRA, gpio::InputOutputAnalogPinType, SIG, GPIONUM>,
+) -> (RushAnyPin, Result<u16, RushError>)
+where
+    RA: gpio::BankGpioRegisterAccess,
+    IRA: gpio::InteruptStatusRegisterAccess,
+    SIG: gpio::GpioSignal,
+    gpio::GpioPin<gpio::Analog, RA, IRA, gpio::InputOutputAnalogPinType, SIG, GPIONUM>:
+        Channel<ADC1, ID = u8>,
+    RushSinglePin<RA, IRA, SIG, GPIONUM>: Into<RushAnyPin>,
+{
+    let mut adc1_config = AdcConfig::new();
+    let mut adc_pin = adc1_config.enable_pin(pin, Attenuation::Attenuation11dB);
+    let raw = match ADC::<ADC1>::adc(adc1, adc1_config) {
+        Err(_) => Err(RushError::Hardware(
+            "esp_hal_common::adc::ADC::adc() failed",
+        )),
+        Ok(mut adc) => loop {
+            match adc.read(&mut adc_pin) {
+                Ok(raw) => break Ok(raw),
+                Err(nb::Error::WouldBlock) => (),
+                Err(nb::Error::Other(_)) => {
+                    break Err(RushError::Hardware(
+                        "embedded_hal::adc::OneShot.read() failed",
+                    ))
+                }
+            }
+        },
+    };
+    (RushSinglePin::AdcAnalogPin(adc_pin.pin).into(), raw)
+}
+
 #[rustfmt::skip]
 pub enum RushSinglePin<RA, IRA, SIG, const GPIONUM: u8>
 where
@@ -230,20 +1673,161 @@ where
     IRA: gpio::InteruptStatusRegisterAccess,
     SIG: gpio::GpioSignal,
 {
-    UnknownAnalogPin (gpio::GpioPin<gpio::Unknown               , RA, IRA, gpio::InputOutputAnalogPinType, SIG, GPIONUM>),
-    InputAnalogPin   (gpio::GpioPin<gpio::Input<gpio::Floating> , RA, IRA, gpio::InputOutputAnalogPinType, SIG, GPIONUM>),
-    OutputAnalogPin  (gpio::GpioPin<gpio::Output<gpio::PushPull>, RA, IRA, gpio::InputOutputAnalogPinType, SIG, GPIONUM>),
-    UnknownDigitalPin(gpio::GpioPin<gpio::Unknown               , RA, IRA, gpio::InputOutputPinType      , SIG, GPIONUM>),
-    InputDigitalPin  (gpio::GpioPin<gpio::Input<gpio::Floating> , RA, IRA, gpio::InputOutputPinType      , SIG, GPIONUM>),
-    OutputDigitalPin (gpio::GpioPin<gpio::Output<gpio::PushPull>, RA, IRA, gpio::InputOutputPinType      , SIG, GPIONUM>),
+    UnknownAnalogPin   (gpio::GpioPin<gpio::Unknown               , RA, IRA, gpio::InputOutputAnalogPinType, SIG, GPIONUM>),
+    InputAnalogPin     (gpio::GpioPin<gpio::Input<gpio::Floating> , RA, IRA, gpio::InputOutputAnalogPinType, SIG, GPIONUM>),
+    PullUpAnalogPin    (gpio::GpioPin<gpio::Input<gpio::PullUp>   , RA, IRA, gpio::InputOutputAnalogPinType, SIG, GPIONUM>),
+    PullDownAnalogPin  (gpio::GpioPin<gpio::Input<gpio::PullDown> , RA, IRA, gpio::InputOutputAnalogPinType, SIG, GPIONUM>),
+    OutputAnalogPin    (gpio::GpioPin<gpio::Output<gpio::PushPull>, RA, IRA, gpio::InputOutputAnalogPinType, SIG, GPIONUM>),
+    OpenDrainAnalogPin (gpio::GpioPin<gpio::Output<gpio::OpenDrain>, RA, IRA, gpio::InputOutputAnalogPinType, SIG, GPIONUM>),
+    AdcAnalogPin       (gpio::GpioPin<gpio::Analog                , RA, IRA, gpio::InputOutputAnalogPinType, SIG, GPIONUM>),
+    UnknownDigitalPin  (gpio::GpioPin<gpio::Unknown               , RA, IRA, gpio::InputOutputPinType      , SIG, GPIONUM>),
+    InputDigitalPin    (gpio::GpioPin<gpio::Input<gpio::Floating> , RA, IRA, gpio::InputOutputPinType      , SIG, GPIONUM>),
+    PullUpDigitalPin   (gpio::GpioPin<gpio::Input<gpio::PullUp>   , RA, IRA, gpio::InputOutputPinType      , SIG, GPIONUM>),
+    PullDownDigitalPin (gpio::GpioPin<gpio::Input<gpio::PullDown> , RA, IRA, gpio::InputOutputPinType      , SIG, GPIONUM>),
+    OutputDigitalPin   (gpio::GpioPin<gpio::Output<gpio::PushPull>, RA, IRA, gpio::InputOutputPinType      , SIG, GPIONUM>),
+    OpenDrainDigitalPin(gpio::GpioPin<gpio::Output<gpio::OpenDrain>, RA, IRA, gpio::InputOutputPinType      , SIG, GPIONUM>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RushPinMode {
+    Unknown,
+    Floating,
+    PullUp,
+    PullDown,
+    PushPull,
+    OpenDrain,
+    Analog,
+}
+
+impl RushPinMode {
+    pub fn is_input(&self) -> bool {
+        matches!(self, Self::Floating | Self::PullUp | Self::PullDown)
+    }
+    pub fn is_output(&self) -> bool {
+        matches!(self, Self::PushPull | Self::OpenDrain)
+    }
+}
+
+impl fmt::Display for RushPinMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Unknown => "unknown",
+            Self::Floating => "floating",
+            Self::PullUp => "pullup",
+            Self::PullDown => "pulldown",
+            Self::PushPull => "pushpull",
+            Self::OpenDrain => "opendrain",
+            Self::Analog => "analog",
+        })
+    }
 }
 
 #[enum_dispatch(RushAnyPin)]
 trait RushSinglePinOperations {
+    fn mode(&self) -> RushPinMode;
+    fn is_analog(&self) -> bool;
+    fn to_mode(self, mode: RushPinMode) -> Self;
     fn to_input(self) -> Self;
     fn to_output(self) -> Self;
-    fn read_state<'a, 'b>(&'a self) -> Result<bool, &'b str>;
-    fn set_state<'a, 'b>(&'a mut self, state: PinState) -> Result<(), &'b str>;
+    fn read_state<'a>(&'a self) -> Result<bool, RushError>;
+    fn set_state<'a>(&'a mut self, state: PinState) -> Result<(), RushError>;
+    fn start_pwm<'a>(
+        &'a mut self,
+        ledc: &'a LEDC<'a>,
+        channel: channel::Number,
+        timer: &'a timer::Timer<'a, LowSpeed>,
+        duty_pct: u8,
+    ) -> Result<(), RushError>;
+    fn connect_peripheral(
+        &mut self,
+        output: Option<gpio::OutputSignal>,
+        input: Option<gpio::InputSignal>,
+        open_drain: bool,
+    );
+    fn disconnect_peripheral(&mut self, input: Option<gpio::InputSignal>);
+}
+
+impl<RA, IRA, SIG, const GPIONUM: u8> RushSinglePin<RA, IRA, SIG, GPIONUM>
+where
+    RA: gpio::BankGpioRegisterAccess,
+    IRA: gpio::InteruptStatusRegisterAccess,
+    SIG: gpio::GpioSignal,
+{
+    // there is no way back to gpio::Unknown, so RushPinMode::Unknown falls back to a floating input
+    fn analog_pin_to_mode<MODE>(
+        pin: gpio::GpioPin<MODE, RA, IRA, gpio::InputOutputAnalogPinType, SIG, GPIONUM>,
+        mode: RushPinMode,
+    ) -> Self {
+        match mode {
+            RushPinMode::Unknown | RushPinMode::Floating => {
+                Self::InputAnalogPin(pin.into_floating_input())
+            }
+            RushPinMode::PullUp => Self::PullUpAnalogPin(pin.into_pull_up_input()),
+            RushPinMode::PullDown => Self::PullDownAnalogPin(pin.into_pull_down_input()),
+            RushPinMode::PushPull => Self::OutputAnalogPin(pin.into_push_pull_output()),
+            RushPinMode::OpenDrain => Self::OpenDrainAnalogPin(pin.into_open_drain_output()),
+            RushPinMode::Analog => Self::AdcAnalogPin(pin.into_analog()),
+        }
+    }
+    // waits for the gpio edge interrupt - the level itself has to be read afterwards
+    async fn wait_for_any_edge<'a>(&'a mut self) -> Result<(), RushError> {
+        match self {
+            Self::InputAnalogPin(p) => match p.wait_for_any_edge().await {
+                Ok(()) => Ok(()),
+                Err(_) => Err(RushError::Hardware(
+                    "embedded_hal_async::digital::Wait.wait_for_any_edge() failed",
+                )),
+            },
+            Self::PullUpAnalogPin(p) => match p.wait_for_any_edge().await {
+                Ok(()) => Ok(()),
+                Err(_) => Err(RushError::Hardware(
+                    "embedded_hal_async::digital::Wait.wait_for_any_edge() failed",
+                )),
+            },
+            Self::PullDownAnalogPin(p) => match p.wait_for_any_edge().await {
+                Ok(()) => Ok(()),
+                Err(_) => Err(RushError::Hardware(
+                    "embedded_hal_async::digital::Wait.wait_for_any_edge() failed",
+                )),
+            },
+            Self::InputDigitalPin(p) => match p.wait_for_any_edge().await {
+                Ok(()) => Ok(()),
+                Err(_) => Err(RushError::Hardware(
+                    "embedded_hal_async::digital::Wait.wait_for_any_edge() failed",
+                )),
+            },
+            Self::PullUpDigitalPin(p) => match p.wait_for_any_edge().await {
+                Ok(()) => Ok(()),
+                Err(_) => Err(RushError::Hardware(
+                    "embedded_hal_async::digital::Wait.wait_for_any_edge() failed",
+                )),
+            },
+            Self::PullDownDigitalPin(p) => match p.wait_for_any_edge().await {
+                Ok(()) => Ok(()),
+                Err(_) => Err(RushError::Hardware(
+                    "embedded_hal_async::digital::Wait.wait_for_any_edge() failed",
+                )),
+            },
+            _ => Err(RushError::WrongMode(
+                "wait_for_any_edge() was called on a non-input pin",
+            )),
+        }
+    }
+    // digital pins have no analog function - set_pin_mode() refuses that, anything else ends up as a floating input
+    fn digital_pin_to_mode<MODE>(
+        pin: gpio::GpioPin<MODE, RA, IRA, gpio::InputOutputPinType, SIG, GPIONUM>,
+        mode: RushPinMode,
+    ) -> Self {
+        match mode {
+            RushPinMode::Unknown | RushPinMode::Floating | RushPinMode::Analog => {
+                Self::InputDigitalPin(pin.into_floating_input())
+            }
+            RushPinMode::PullUp => Self::PullUpDigitalPin(pin.into_pull_up_input()),
+            RushPinMode::PullDown => Self::PullDownDigitalPin(pin.into_pull_down_input()),
+            RushPinMode::PushPull => Self::OutputDigitalPin(pin.into_push_pull_output()),
+            RushPinMode::OpenDrain => Self::OpenDrainDigitalPin(pin.into_open_drain_output()),
+        }
+    }
 }
 
 impl<RA, IRA, SIG, const GPIONUM: u8> RushSinglePinOperations
@@ -253,48 +1837,397 @@ where
     IRA: gpio::InteruptStatusRegisterAccess,
     SIG: gpio::GpioSignal,
 {
-    fn to_input(self) -> Self {
+    fn mode(&self) -> RushPinMode {
         match self {
-            Self::OutputAnalogPin(p) => Self::InputAnalogPin(p.into_floating_input()),
-            Self::UnknownAnalogPin(p) => Self::InputAnalogPin(p.into_floating_input()),
-            Self::OutputDigitalPin(p) => Self::InputDigitalPin(p.into_floating_input()),
-            Self::UnknownDigitalPin(p) => Self::InputDigitalPin(p.into_floating_input()),
-            _ => self,
+            Self::UnknownAnalogPin(_) | Self::UnknownDigitalPin(_) => RushPinMode::Unknown,
+            Self::InputAnalogPin(_) | Self::InputDigitalPin(_) => RushPinMode::Floating,
+            Self::PullUpAnalogPin(_) | Self::PullUpDigitalPin(_) => RushPinMode::PullUp,
+            Self::PullDownAnalogPin(_) | Self::PullDownDigitalPin(_) => RushPinMode::PullDown,
+            Self::OutputAnalogPin(_) | Self::OutputDigitalPin(_) => RushPinMode::PushPull,
+            Self::OpenDrainAnalogPin(_) | Self::OpenDrainDigitalPin(_) => RushPinMode::OpenDrain,
+            Self::AdcAnalogPin(_) => RushPinMode::Analog,
         }
     }
-    fn to_output(self) -> Self {
+    fn is_analog(&self) -> bool {
         match self {
-            Self::InputAnalogPin(p) => Self::OutputAnalogPin(p.into_push_pull_output()),
-            Self::UnknownAnalogPin(p) => Self::OutputAnalogPin(p.into_push_pull_output()),
-            Self::InputDigitalPin(p) => Self::OutputDigitalPin(p.into_push_pull_output()),
-            Self::UnknownDigitalPin(p) => Self::OutputDigitalPin(p.into_push_pull_output()),
-            _ => self,
+            Self::UnknownAnalogPin(_)
+            | Self::InputAnalogPin(_)
+            | Self::PullUpAnalogPin(_)
+            | Self::PullDownAnalogPin(_)
+            | Self::OutputAnalogPin(_)
+            | Self::OpenDrainAnalogPin(_)
+            | Self::AdcAnalogPin(_) => true,
+            _ => false,
         }
     }
-    fn read_state<'a, 'b>(&'a self) -> Result<bool, &'b str> {
+    fn to_mode(self, mode: RushPinMode) -> Self {
+        if self.mode() == mode {
+            return self;
+        }
+        match self {
+            Self::UnknownAnalogPin(p) => Self::analog_pin_to_mode(p, mode),
+            Self::InputAnalogPin(p) => Self::analog_pin_to_mode(p, mode),
+            Self::PullUpAnalogPin(p) => Self::analog_pin_to_mode(p, mode),
+            Self::PullDownAnalogPin(p) => Self::analog_pin_to_mode(p, mode),
+            Self::OutputAnalogPin(p) => Self::analog_pin_to_mode(p, mode),
+            Self::OpenDrainAnalogPin(p) => Self::analog_pin_to_mode(p, mode),
+            Self::AdcAnalogPin(p) => Self::analog_pin_to_mode(p, mode),
+            Self::UnknownDigitalPin(p) => Self::digital_pin_to_mode(p, mode),
+            Self::InputDigitalPin(p) => Self::digital_pin_to_mode(p, mode),
+            Self::PullUpDigitalPin(p) => Self::digital_pin_to_mode(p, mode),
+            Self::PullDownDigitalPin(p) => Self::digital_pin_to_mode(p, mode),
+            Self::OutputDigitalPin(p) => Self::digital_pin_to_mode(p, mode),
+            Self::OpenDrainDigitalPin(p) => Self::digital_pin_to_mode(p, mode),
+        }
+    }
+    // inputs keep their pull resistor configuration - everything else becomes a floating input
+    fn to_input(self) -> Self {
+        if self.mode().is_input() {
+            return self;
+        }
+        self.to_mode(RushPinMode::Floating)
+    }
+    // open drain outputs stay open drain - everything else becomes a push pull output
+    fn to_output(self) -> Self {
+        if self.mode().is_output() {
+            return self;
+        }
+        self.to_mode(RushPinMode::PushPull)
+    }
+    fn read_state<'a>(&'a self) -> Result<bool, RushError> {
         match self {
             Self::InputAnalogPin(p) => match p.is_high() {
                 Ok(b) => Ok(b),
-                Err(_) => Err("esp_hal_common::gpio::GpioPin.is_high() failed"),
+                Err(_) => Err(RushError::Hardware(
+                    "esp_hal_common::gpio::GpioPin.is_high() failed",
+                )),
+            },
+            Self::PullUpAnalogPin(p) => match p.is_high() {
+                Ok(b) => Ok(b),
+                Err(_) => Err(RushError::Hardware(
+                    "esp_hal_common::gpio::GpioPin.is_high() failed",
+                )),
+            },
+            Self::PullDownAnalogPin(p) => match p.is_high() {
+                Ok(b) => Ok(b),
+                Err(_) => Err(RushError::Hardware(
+                    "esp_hal_common::gpio::GpioPin.is_high() failed",
+                )),
             },
             Self::InputDigitalPin(p) => match p.is_high() {
                 Ok(b) => Ok(b),
-                Err(_) => Err("esp_hal_common::gpio::GpioPin.is_high() failed"),
+                Err(_) => Err(RushError::Hardware(
+                    "esp_hal_common::gpio::GpioPin.is_high() failed",
+                )),
+            },
+            Self::PullUpDigitalPin(p) => match p.is_high() {
+                Ok(b) => Ok(b),
+                Err(_) => Err(RushError::Hardware(
+                    "esp_hal_common::gpio::GpioPin.is_high() failed",
+                )),
+            },
+            Self::PullDownDigitalPin(p) => match p.is_high() {
+                Ok(b) => Ok(b),
+                Err(_) => Err(RushError::Hardware(
+                    "esp_hal_common::gpio::GpioPin.is_high() failed",
+                )),
+            },
+            Self::OutputAnalogPin(p) => match p.is_set_high() {
+                Ok(b) => Ok(b),
+                Err(_) => Err(RushError::Hardware(
+                    "embedded_hal::digital::v2::StatefulOutputPin.is_set_high() failed",
+                )),
+            },
+            Self::OpenDrainAnalogPin(p) => match p.is_set_high() {
+                Ok(b) => Ok(b),
+                Err(_) => Err(RushError::Hardware(
+                    "embedded_hal::digital::v2::StatefulOutputPin.is_set_high() failed",
+                )),
             },
-            _ => Err("read_state() was called on a non-input pin"),
+            Self::OutputDigitalPin(p) => match p.is_set_high() {
+                Ok(b) => Ok(b),
+                Err(_) => Err(RushError::Hardware(
+                    "embedded_hal::digital::v2::StatefulOutputPin.is_set_high() failed",
+                )),
+            },
+            Self::OpenDrainDigitalPin(p) => match p.is_set_high() {
+                Ok(b) => Ok(b),
+                Err(_) => Err(RushError::Hardware(
+                    "embedded_hal::digital::v2::StatefulOutputPin.is_set_high() failed",
+                )),
+            },
+            _ => Err(RushError::WrongMode(
+                "read_state() was called on a pin of unknown or analog mode",
+            )),
         }
     }
-    fn set_state<'a, 'b>(&'a mut self, state: PinState) -> Result<(), &'b str> {
+    fn set_state<'a>(&'a mut self, state: PinState) -> Result<(), RushError> {
         match self {
             Self::OutputAnalogPin(p) => match p.set_state(state) {
                 Ok(_) => Ok(()),
-                Err(_) => Err("embedded_hal::digital::v2::OutputPin.set_state() failed"),
+                Err(_) => Err(RushError::Hardware(
+                    "embedded_hal::digital::v2::OutputPin.set_state() failed",
+                )),
+            },
+            Self::OpenDrainAnalogPin(p) => match p.set_state(state) {
+                Ok(_) => Ok(()),
+                Err(_) => Err(RushError::Hardware(
+                    "embedded_hal::digital::v2::OutputPin.set_state() failed",
+                )),
             },
             Self::OutputDigitalPin(p) => match p.set_state(state) {
                 Ok(_) => Ok(()),
-                Err(_) => Err("embedded_hal::digital::v2::OutputPin.set_state() failed"),
+                Err(_) => Err(RushError::Hardware(
+                    "embedded_hal::digital::v2::OutputPin.set_state() failed",
+                )),
+            },
+            Self::OpenDrainDigitalPin(p) => match p.set_state(state) {
+                Ok(_) => Ok(()),
+                Err(_) => Err(RushError::Hardware(
+                    "embedded_hal::digital::v2::OutputPin.set_state() failed",
+                )),
+            },
+            _ => Err(RushError::WrongMode(
+                "set_state() was called on a non-output pin",
+            )),
+        }
+    }
+    // routes the ledc channel to the pin - the channel object itself can be dropped afterwards
+    fn start_pwm<'a>(
+        &'a mut self,
+        ledc: &'a LEDC<'a>,
+        channel: channel::Number,
+        timer: &'a timer::Timer<'a, LowSpeed>,
+        duty_pct: u8,
+    ) -> Result<(), RushError> {
+        let channel_config = channel::config::Config { timer, duty_pct };
+        match self {
+            Self::OutputAnalogPin(p) => {
+                match ledc.get_channel(channel, p).configure(channel_config) {
+                    Ok(()) => Ok(()),
+                    Err(_) => Err(RushError::Hardware(
+                        "esp_hal_common::ledc::channel::Channel.configure() failed",
+                    )),
+                }
+            }
+            Self::OutputDigitalPin(p) => {
+                match ledc.get_channel(channel, p).configure(channel_config) {
+                    Ok(()) => Ok(()),
+                    Err(_) => Err(RushError::Hardware(
+                        "esp_hal_common::ledc::channel::Channel.configure() failed",
+                    )),
+                }
+            }
+            _ => Err(RushError::WrongMode(
+                "start_pwm() was called on a non push pull pin",
+            )),
+        }
+    }
+    // hands the pin to a peripheral through the gpio matrix - the pin keeps its type,
+    // but not its function, until disconnect_peripheral() is called
+    fn connect_peripheral(
+        &mut self,
+        output: Option<gpio::OutputSignal>,
+        input: Option<gpio::InputSignal>,
+        open_drain: bool,
+    ) {
+        match self {
+            Self::UnknownAnalogPin(p) => connect_peripheral(p, output, input, open_drain),
+            Self::InputAnalogPin(p) => connect_peripheral(p, output, input, open_drain),
+            Self::PullUpAnalogPin(p) => connect_peripheral(p, output, input, open_drain),
+            Self::PullDownAnalogPin(p) => connect_peripheral(p, output, input, open_drain),
+            Self::OutputAnalogPin(p) => connect_peripheral(p, output, input, open_drain),
+            Self::OpenDrainAnalogPin(p) => connect_peripheral(p, output, input, open_drain),
+            Self::AdcAnalogPin(p) => connect_peripheral(p, output, input, open_drain),
+            Self::UnknownDigitalPin(p) => connect_peripheral(p, output, input, open_drain),
+            Self::InputDigitalPin(p) => connect_peripheral(p, output, input, open_drain),
+            Self::PullUpDigitalPin(p) => connect_peripheral(p, output, input, open_drain),
+            Self::PullDownDigitalPin(p) => connect_peripheral(p, output, input, open_drain),
+            Self::OutputDigitalPin(p) => connect_peripheral(p, output, input, open_drain),
+            Self::OpenDrainDigitalPin(p) => connect_peripheral(p, output, input, open_drain),
+        }
+    }
+    fn disconnect_peripheral(&mut self, input: Option<gpio::InputSignal>) {
+        match self {
+            Self::UnknownAnalogPin(p) => disconnect_peripheral(p, input),
+            Self::InputAnalogPin(p) => disconnect_peripheral(p, input),
+            Self::PullUpAnalogPin(p) => disconnect_peripheral(p, input),
+            Self::PullDownAnalogPin(p) => disconnect_peripheral(p, input),
+            Self::OutputAnalogPin(p) => disconnect_peripheral(p, input),
+            Self::OpenDrainAnalogPin(p) => disconnect_peripheral(p, input),
+            Self::AdcAnalogPin(p) => disconnect_peripheral(p, input),
+            Self::UnknownDigitalPin(p) => disconnect_peripheral(p, input),
+            Self::InputDigitalPin(p) => disconnect_peripheral(p, input),
+            Self::PullUpDigitalPin(p) => disconnect_peripheral(p, input),
+            Self::PullDownDigitalPin(p) => disconnect_peripheral(p, input),
+            Self::OutputDigitalPin(p) => disconnect_peripheral(p, input),
+            Self::OpenDrainDigitalPin(p) => disconnect_peripheral(p, input),
+        }
+    }
+}
+
+struct SpiSignals {
+    sck: gpio::OutputSignal,
+    mosi: gpio::OutputSignal,
+    miso: gpio::InputSignal,
+    cs: gpio::OutputSignal,
+}
+
+// the owner name shown by list_pins() and the gpio matrix signals of the bus
+fn spi_bus_signals(bus: u8) -> Result<(&'static str, SpiSignals), RushError> {
+    match bus {
+        2 => Ok((
+            "spi.2",
+            SpiSignals {
+                sck: gpio::OutputSignal::FSPICLK,
+                mosi: gpio::OutputSignal::FSPID,
+                miso: gpio::InputSignal::FSPIQ,
+                cs: gpio::OutputSignal::FSPICS0,
             },
-            _ => Err("set_state() was called on a non-output pin"),
+        )),
+        3 => Ok((
+            "spi.3",
+            SpiSignals {
+                sck: gpio::OutputSignal::SPI3_CLK,
+                mosi: gpio::OutputSignal::SPI3_D,
+                miso: gpio::InputSignal::SPI3_Q,
+                cs: gpio::OutputSignal::SPI3_CS0,
+            },
+        )),
+        _ => Err(RushError::NoSuchPin(
+            "spi bus does not exist - use spi.2 or spi.3",
+        )),
+    }
+}
+
+fn pcnt_signal(unit_index: usize) -> gpio::InputSignal {
+    match unit_index {
+        0 => gpio::InputSignal::PCNT0_SIG_CH0,
+        1 => gpio::InputSignal::PCNT1_SIG_CH0,
+        2 => gpio::InputSignal::PCNT2_SIG_CH0,
+        _ => gpio::InputSignal::PCNT3_SIG_CH0,
+    }
+}
+
+#[esp32s3_hal::macros::interrupt]
+fn PCNT() {
+    PCNT_DRIVER.lock(|pcnt| {
+        if let Some(pcnt) = &*pcnt.borrow() {
+            for (number, overflows) in PCNT_UNITS.iter().zip(PCNT_OVERFLOWS.iter()) {
+                let mut unit = pcnt.get_unit(*number);
+                if unit.interrupt_set() {
+                    if unit.get_events().high_limit {
+                        // saturates instead of wrapping, so a count never jumps back to zero
+                        overflows
+                            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_add(1))
+                            .ok();
+                    }
+                    unit.reset_interrupt();
+                }
+            }
+        }
+    });
+}
+
+fn i2c_error(err: esp32s3_hal::i2c::Error) -> RushError {
+    match err {
+        esp32s3_hal::i2c::Error::AckCheckFailed => {
+            RushError::Hardware("device did not acknowledge")
+        }
+        esp32s3_hal::i2c::Error::TimeOut => {
+            RushError::Timeout("bus timed out - are there pull-up resistors?")
+        }
+        esp32s3_hal::i2c::Error::ExceedingFifo => {
+            RushError::InvalidValue("too many bytes for one transfer")
         }
+        _ => RushError::Hardware("esp_hal_common::i2c::I2C transfer failed"),
+    }
+}
+
+// open drain outputs get the internal pull-up, so a bus works without external resistors at low speeds -
+// pins without an output signal stop driving
+fn connect_peripheral<P: gpio::OutputPin + gpio::InputPin>(
+    pin: &mut P,
+    output: Option<gpio::OutputSignal>,
+    input: Option<gpio::InputSignal>,
+    open_drain: bool,
+) {
+    if let Some(signal) = output {
+        if open_drain {
+            pin.set_to_open_drain_output().internal_pull_up(true);
+        } else {
+            pin.set_to_push_pull_output();
+        }
+        pin.connect_peripheral_to_output(signal);
+    } else {
+        pin.enable_output(false);
+    }
+    if let Some(signal) = input {
+        pin.enable_input(true).connect_input_to_peripheral(signal);
+    }
+}
+
+// leaves the pin as a floating input - the caller still has to bring its type in line with to_mode()
+fn disconnect_peripheral<P: gpio::OutputPin + gpio::InputPin>(
+    pin: &mut P,
+    input: Option<gpio::InputSignal>,
+) {
+    if let Some(signal) = input {
+        pin.disconnect_input_from_peripheral(signal);
+    }
+    pin.disconnect_peripheral_from_output()
+        .enable_output(false)
+        .enable_open_drain(false)
+        .internal_pull_up(false)
+        .internal_pull_down(false);
+    pin.enable_input(true);
+}
+
+fn duty_from_bits(bits: u32) -> timer::config::Duty {
+    match bits {
+        1 => timer::config::Duty::Duty1Bit,
+        2 => timer::config::Duty::Duty2Bit,
+        3 => timer::config::Duty::Duty3Bit,
+        4 => timer::config::Duty::Duty4Bit,
+        5 => timer::config::Duty::Duty5Bit,
+        6 => timer::config::Duty::Duty6Bit,
+        7 => timer::config::Duty::Duty7Bit,
+        8 => timer::config::Duty::Duty8Bit,
+        9 => timer::config::Duty::Duty9Bit,
+        10 => timer::config::Duty::Duty10Bit,
+        11 => timer::config::Duty::Duty11Bit,
+        12 => timer::config::Duty::Duty12Bit,
+        13 => timer::config::Duty::Duty13Bit,
+        _ => timer::config::Duty::Duty14Bit,
+    }
+}
+
+// the channel stops toggling and drives its idle level, low, until it is configured again - esp32s3-hal 0.8 has no
+// way to stop a channel, so this clears sig_out_en the way the hal sets it in Channel::configure()
+fn stop_pwm_channel(channel_index: usize) {
+    // safety: the ledc is only configured through the pin manager, which holds its peripheral
+    let ledc = unsafe { &*esp32s3_hal::peripherals::LEDC::PTR };
+    macro_rules! stop {
+        ($conf0:ident) => {
+            ledc.$conf0.modify(|_, w| {
+                w.sig_out_en()
+                    .clear_bit()
+                    .idle_lv()
+                    .clear_bit()
+                    .para_up()
+                    .set_bit()
+            })
+        };
+    }
+    match channel_index {
+        0 => stop!(ch0_conf0),
+        1 => stop!(ch1_conf0),
+        2 => stop!(ch2_conf0),
+        3 => stop!(ch3_conf0),
+        4 => stop!(ch4_conf0),
+        5 => stop!(ch5_conf0),
+        6 => stop!(ch6_conf0),
+        7 => stop!(ch7_conf0),
+        _ => (),
     }
 }
@@ -0,0 +1,80 @@
+// clients switch to json with `format json` - commands and events answer with a RushResponse, which is written in
+// the format of the client here. see rush_common::rush_response for what both formats look like.
+// commands sent as `#[req] [command]` get their request id back - in text every line of the response starts with
+// `#[req] `, in json the object has a `req` member
+pub use rush_common::rush_response::{RushFormat, RushId, RushResponse, RushValue};
+
+use core::str::from_utf8;
+use rush_common::rush_response::{JsonResponse, JsonSource, LinePrefix, TextResponse};
+use stackfmt::fmt_truncate;
+
+// the response to a single command - `cmd` is the command as typed, e.g. read
+pub fn format_response<'b>(
+    fmt_buffer: &'b mut [u8],
+    format: RushFormat,
+    req: Option<&str>,
+    cmd: &str,
+    response: &RushResponse,
+) -> &'b str {
+    match format {
+        RushFormat::Text => {
+            let prefix = req.map_or(LinePrefix::None, LinePrefix::Request);
+            fmt_truncate(
+                fmt_buffer,
+                format_args!("{}", TextResponse { response, prefix }),
+            )
+        }
+        RushFormat::Json => {
+            let source = JsonSource::Command(cmd);
+            let object = JsonResponse {
+                response,
+                req,
+                source,
+            };
+            object.write(fmt_buffer)
+        }
+    }
+}
+
+// an unsolicited message - `event` names what caused it, e.g. watch. json objects of events always stand out,
+// as they have no `cmd`
+pub fn format_event<'b>(
+    fmt_buffer: &'b mut [u8],
+    format: RushFormat,
+    mark: bool,
+    event: &str,
+    response: &RushResponse,
+) -> &'b str {
+    match format {
+        RushFormat::Text => {
+            let prefix = if mark {
+                LinePrefix::Event
+            } else {
+                LinePrefix::None
+            };
+            fmt_truncate(
+                fmt_buffer,
+                format_args!("{}", TextResponse { response, prefix }),
+            )
+        }
+        RushFormat::Json => {
+            let source = JsonSource::Event(event);
+            let object = JsonResponse {
+                response,
+                req: None,
+                source,
+            };
+            object.write(fmt_buffer)
+        }
+    }
+}
+
+// formats into the front of the buffer and hands back the rest, so a response can hold more than one string
+pub fn fmt_split<'b>(
+    fmt_buffer: &'b mut [u8],
+    args: core::fmt::Arguments,
+) -> (&'b str, &'b mut [u8]) {
+    let len = fmt_truncate(fmt_buffer, args).len();
+    let (text, rest) = fmt_buffer.split_at_mut(len);
+    (from_utf8(text).unwrap_or_default(), rest)
+}
@@ -0,0 +1,128 @@
+use esp32s3_hal::gpio::{
+    AlternateFunction, DriveStrength, Event, InputPin, InputSignal, OutputPin, OutputSignal, Pin,
+};
+use esp32s3_hal::peripheral::Peripheral;
+
+// stands in for a gpio pin when constructing a hal peripheral driver - the drivers only use their pins to
+// route signals, which RushPinManager already did with the actual pin, so every call is ignored here.
+// this way a driver does not have to be generic over the 45 pin types, which rush only knows at runtime.
+// it still carries the number of the pin it stands in for, so number() reports the real pin instead of
+// gpio 0 - a strapping pin that the hal would otherwise be told about whenever a driver asks
+pub struct RushRoutedPin(pub u8);
+
+impl Peripheral for RushRoutedPin {
+    type P = RushRoutedPin;
+
+    unsafe fn clone_unchecked(&mut self) -> Self::P {
+        RushRoutedPin(self.0)
+    }
+}
+
+impl Pin for RushRoutedPin {
+    fn number(&self) -> u8 {
+        self.0
+    }
+    fn sleep_mode(&mut self, _on: bool) {}
+    fn set_alternate_function(&mut self, _alternate: AlternateFunction) {}
+    fn is_listening(&self) -> bool {
+        false
+    }
+    fn listen_with_options(
+        &mut self,
+        _event: Event,
+        _int_enable: bool,
+        _nmi_enable: bool,
+        _wake_up_from_light_sleep: bool,
+    ) {
+    }
+    fn unlisten(&mut self) {}
+    fn clear_interrupt(&mut self) {}
+    fn is_pcore_interrupt_set(&self) -> bool {
+        false
+    }
+    fn is_pcore_non_maskable_interrupt_set(&self) -> bool {
+        false
+    }
+    fn is_acore_interrupt_set(&self) -> bool {
+        false
+    }
+    fn is_acore_non_maskable_interrupt_set(&self) -> bool {
+        false
+    }
+    fn enable_hold(&mut self, _on: bool) {}
+}
+
+impl InputPin for RushRoutedPin {
+    fn set_to_input(&mut self) -> &mut Self {
+        self
+    }
+    fn enable_input(&mut self, _on: bool) -> &mut Self {
+        self
+    }
+    fn enable_input_in_sleep_mode(&mut self, _on: bool) -> &mut Self {
+        self
+    }
+    fn is_input_high(&self) -> bool {
+        false
+    }
+    fn connect_input_to_peripheral_with_options(
+        &mut self,
+        _signal: InputSignal,
+        _invert: bool,
+        _force_via_gpio_mux: bool,
+    ) -> &mut Self {
+        self
+    }
+    fn disconnect_input_from_peripheral(&mut self, _signal: InputSignal) -> &mut Self {
+        self
+    }
+}
+
+impl OutputPin for RushRoutedPin {
+    fn set_to_open_drain_output(&mut self) -> &mut Self {
+        self
+    }
+    fn set_to_push_pull_output(&mut self) -> &mut Self {
+        self
+    }
+    fn enable_output(&mut self, _on: bool) -> &mut Self {
+        self
+    }
+    fn set_output_high(&mut self, _on: bool) -> &mut Self {
+        self
+    }
+    fn set_drive_strength(&mut self, _strength: DriveStrength) -> &mut Self {
+        self
+    }
+    fn enable_open_drain(&mut self, _on: bool) -> &mut Self {
+        self
+    }
+    fn enable_output_in_sleep_mode(&mut self, _on: bool) -> &mut Self {
+        self
+    }
+    fn internal_pull_up_in_sleep_mode(&mut self, _on: bool) -> &mut Self {
+        self
+    }
+    fn internal_pull_down_in_sleep_mode(&mut self, _on: bool) -> &mut Self {
+        self
+    }
+    fn connect_peripheral_to_output_with_options(
+        &mut self,
+        _signal: OutputSignal,
+        _invert: bool,
+        _invert_enable: bool,
+        _enable_from_gpio: bool,
+        _force_via_gpio_mux: bool,
+    ) -> &mut Self {
+        self
+    }
+    fn disconnect_peripheral_from_output(&mut self) -> &mut Self {
+        self
+    }
+    fn internal_pull_up(&mut self, _on: bool) -> &mut Self {
+        self
+    }
+    fn internal_pull_down(&mut self, _on: bool) -> &mut Self {
+        self
+    }
+}
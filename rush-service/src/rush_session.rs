@@ -0,0 +1,467 @@
+use crate::rush_error::{format_stopped, RushError};
+use crate::rush_pin_manager::{RushPinEvent, RushPinManager};
+use crate::rush_response::{fmt_split, format_event, RushFormat, RushId, RushResponse, RushValue};
+
+use core::str::from_utf8;
+use embassy_time::{Duration, Instant};
+use stackfmt::fmt_truncate;
+
+#[derive(Clone, Copy)]
+struct RushFrequencyGate {
+    start: Instant,
+    end: Instant,
+    start_count: u32,
+    started_counter: bool, // the pulse counter only runs for this measurement and is stopped afterwards
+}
+
+// how often a bridged uart is drained - its fifo holds 128 bytes, about 11ms worth of data at 115200 baud
+const UART_POLL_INTERVAL: Duration = Duration::from_millis(5);
+const UART_LINE_LEN: usize = 128;
+
+#[derive(Clone, Copy)]
+struct RushShout {
+    interval: Duration,
+    next_shout: Instant,
+}
+
+// everything that belongs to a single client connection - the pin manager itself is shared by all clients
+pub struct RushSession {
+    pub client_id: u8,
+    pub format: RushFormat,
+    pub mark_events: bool, // set by the first command with a request id - events are marked from then on
+    watched_pins: u64,     // one bit per gpio
+    shouts: [Option<RushShout>; 49],
+    frequency_gates: [Option<RushFrequencyGate>; 49],
+    uart_line: Option<heapless::Vec<u8, UART_LINE_LEN>>, // Some while the uart is bridged to this client
+}
+
+impl RushSession {
+    pub fn new(client_id: u8) -> Self {
+        RushSession {
+            client_id,
+            format: RushFormat::Text,
+            mark_events: false,
+            watched_pins: 0,
+            shouts: [None; 49],
+            frequency_gates: [None; 49],
+            uart_line: None,
+        }
+    }
+
+    pub fn watch(&mut self, pin: u8) {
+        self.watched_pins |= 1 << pin;
+    }
+
+    pub fn unwatch(&mut self, pin: u8) {
+        self.watched_pins &= !(1 << pin);
+    }
+
+    pub fn is_watching(&self, pin: u8) -> bool {
+        pin < 64 && self.watched_pins & (1 << pin) != 0
+    }
+
+    pub fn shout(&mut self, pin: u8, interval: Duration) -> Result<(), RushError> {
+        if interval.as_ticks() == 0 {
+            return Err(RushError::InvalidValue(
+                "interval must be greater than zero",
+            ));
+        }
+        match self.shouts.get_mut(pin as usize) {
+            None => Err(RushError::NoSuchPin("pin does not exist")),
+            Some(shout) => {
+                *shout = Some(RushShout {
+                    interval,
+                    next_shout: Instant::now() + interval,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    pub fn unshout(&mut self, pin: u8) -> Result<(), RushError> {
+        match self.shouts.get_mut(pin as usize) {
+            None => Err(RushError::NoSuchPin("pin does not exist")),
+            Some(shout) => {
+                *shout = None;
+                Ok(())
+            }
+        }
+    }
+
+    // Instant::MAX if nothing is shouted
+    fn next_shout(&self) -> Instant {
+        self.shouts
+            .iter()
+            .flatten()
+            .map(|shout| shout.next_shout)
+            .fold(Instant::MAX, |next_shout, pin_next_shout| {
+                next_shout.min(pin_next_shout)
+            })
+    }
+
+    // the result is reported by poll_timers() once the gate time is over
+    pub fn measure_frequency(
+        &mut self,
+        pin_manager: &mut RushPinManager,
+        pin: u8,
+        gate: Duration,
+        force: bool,
+    ) -> Result<(), RushError> {
+        if gate.as_ticks() == 0 {
+            return Err(RushError::InvalidValue(
+                "gate time must be greater than zero",
+            ));
+        }
+        match self.frequency_gates.get(pin as usize) {
+            None => return Err(RushError::NoSuchPin("pin does not exist")),
+            Some(Some(_)) => {
+                return Err(RushError::Busy(
+                    "frequency of pin is already being measured",
+                ))
+            }
+            Some(None) => (),
+        }
+
+        // a pin that is counted already keeps counting - its count is not reset by the measurement
+        let started_counter = !pin_manager.is_counted(pin);
+        if started_counter {
+            pin_manager.count_start(pin, force)?;
+        }
+        let start_count = pin_manager.count_read(pin)?;
+        let start = Instant::now();
+        self.frequency_gates[pin as usize] = Some(RushFrequencyGate {
+            start,
+            end: start + gate,
+            start_count,
+            started_counter,
+        });
+        Ok(())
+    }
+
+    // stops the pulse counters this client started - otherwise their pins would stay claimed after a disconnect
+    pub fn cancel_frequency_measurements(&mut self, pin_manager: &mut RushPinManager) {
+        for (pin_num, gate) in self.frequency_gates.iter_mut().enumerate() {
+            if let Some(RushFrequencyGate {
+                started_counter: true,
+                ..
+            }) = gate.take()
+            {
+                pin_manager.count_stop(pin_num as u8).ok();
+            }
+        }
+    }
+
+    // Instant::MAX if nothing is shouted or measured
+    pub fn next_timer(&self) -> Instant {
+        self.frequency_gates
+            .iter()
+            .flatten()
+            .map(|gate| gate.end)
+            .fold(self.next_shout(), |next_timer, gate_end| {
+                next_timer.min(gate_end)
+            })
+    }
+
+    pub fn poll_timers<'b>(
+        &mut self,
+        pin_manager: &mut RushPinManager,
+        fmt_buffer: &'b mut [u8],
+    ) -> &'b str {
+        let mut len = self.poll_shouted_pins(pin_manager, fmt_buffer);
+        len += self.poll_frequency_gates(pin_manager, &mut fmt_buffer[len..]);
+        from_utf8(&fmt_buffer[..len]).unwrap_or_default()
+    }
+
+    // returns the length of the events written
+    fn poll_frequency_gates(
+        &mut self,
+        pin_manager: &mut RushPinManager,
+        fmt_buffer: &mut [u8],
+    ) -> usize {
+        let now = Instant::now();
+        let (format, mark) = (self.format, self.mark_events);
+        let mut len = 0;
+
+        for (pin_num, gate) in self.frequency_gates.iter_mut().enumerate() {
+            let RushFrequencyGate {
+                start,
+                start_count,
+                started_counter,
+                ..
+            } = match *gate {
+                Some(frequency_gate) if now >= frequency_gate.end => frequency_gate,
+                _ => continue,
+            };
+            *gate = None;
+
+            let count = pin_manager.count_read(pin_num as u8);
+            if started_counter {
+                pin_manager.count_stop(pin_num as u8).ok();
+            }
+            let mut text_buffer = [0u8; 128];
+            let response = match count {
+                Ok(count) if count >= start_count => {
+                    // the gate is measured, as the client may have been busy when it ended
+                    let edges = (count - start_count) as u64;
+                    let elapsed_us = (now - start).as_micros().max(1);
+                    let decihertz = edges * 10_000_000 / elapsed_us;
+                    RushResponse::new(fmt_truncate(
+                        &mut text_buffer,
+                        format_args!(
+                            "gpio.{} = {}.{}Hz ({} edges in {}ms)\n",
+                            pin_num,
+                            decihertz / 10,
+                            decihertz % 10,
+                            edges,
+                            elapsed_us / 1000
+                        ),
+                    ))
+                    .id(RushId::Pin("gpio", pin_num as u8))
+                    .value(RushValue::Decimal(decihertz as i64, 1))
+                    .field("edges", RushValue::Number(edges as i64))
+                    .field("ms", RushValue::Number((elapsed_us / 1000) as i64))
+                }
+                Ok(_) => format_stopped(
+                    &mut text_buffer,
+                    RushError::Busy("counter was reset"),
+                    format_args!("stopped measuring gpio.{}", pin_num),
+                ),
+                Err(e) => format_stopped(
+                    &mut text_buffer,
+                    e,
+                    format_args!("stopped measuring gpio.{}", pin_num),
+                ),
+            };
+            len += format_event(&mut fmt_buffer[len..], format, mark, "freq", &response).len();
+        }
+        len
+    }
+
+    // shouted pins report their level once per interval, whether it changed or not - returns the length of the
+    // events written
+    fn poll_shouted_pins(
+        &mut self,
+        pin_manager: &mut RushPinManager,
+        fmt_buffer: &mut [u8],
+    ) -> usize {
+        let now = Instant::now();
+        let (format, mark) = (self.format, self.mark_events);
+        let mut len = 0;
+
+        for (pin_num, shout) in self.shouts.iter_mut().enumerate() {
+            let (interval, next_shout) = match *shout {
+                Some(RushShout {
+                    interval,
+                    next_shout,
+                }) if now >= next_shout => (interval, next_shout),
+                _ => continue,
+            };
+
+            // keep the cadence, but do not try to catch up on shouts missed while busy
+            *shout = Some(RushShout {
+                interval,
+                next_shout: (next_shout + interval).max(now),
+            });
+            let mut text_buffer = [0u8; 128];
+            // the pin passed the policy when the shout was started
+            let response = match pin_manager.read_pin(pin_num as u8, false, true) {
+                Err(e) => {
+                    *shout = None;
+                    format_stopped(
+                        &mut text_buffer,
+                        e,
+                        format_args!("stopped shouting gpio.{}", pin_num),
+                    )
+                }
+                Ok(state) => format_pin_state(&mut text_buffer, pin_num as u8, state),
+            };
+            len += format_event(&mut fmt_buffer[len..], format, mark, "shout", &response).len();
+        }
+        len
+    }
+
+    pub fn start_uart_bridge(&mut self) {
+        if self.uart_line.is_none() {
+            self.uart_line = Some(heapless::Vec::new());
+        }
+    }
+
+    pub fn stop_uart_bridge(&mut self) {
+        self.uart_line = None;
+    }
+
+    pub fn is_bridging_uart(&self) -> bool {
+        self.uart_line.is_some()
+    }
+
+    // Instant::MAX if the uart is not bridged
+    pub fn next_uart_poll(&self) -> Instant {
+        match self.uart_line {
+            None => Instant::MAX,
+            Some(_) => Instant::now() + UART_POLL_INTERVAL,
+        }
+    }
+
+    // received bytes are sent as one `uart: ` line per line - an unfinished line is sent once the uart goes quiet,
+    // so prompts show up without waiting for a newline
+    pub fn poll_uart<'b>(
+        &mut self,
+        pin_manager: &mut RushPinManager,
+        fmt_buffer: &'b mut [u8],
+    ) -> &'b str {
+        let (format, mark) = (self.format, self.mark_events);
+        let line = match &mut self.uart_line {
+            None => return "",
+            Some(line) => line,
+        };
+
+        let mut received = [0u8; UART_LINE_LEN];
+        let received_len = match pin_manager.uart_read(self.client_id, &mut received) {
+            Ok(received_len) => received_len,
+            Err(e) => {
+                self.uart_line = None;
+                let mut text_buffer = [0u8; 128];
+                let response =
+                    format_stopped(&mut text_buffer, e, format_args!("stopped bridging uart"));
+                return format_event(fmt_buffer, format, mark, "uart", &response);
+            }
+        };
+
+        let mut len = 0;
+        for byte in &received[..received_len] {
+            match *byte {
+                b'\n' => (),
+                b'\r' => continue,
+                _ => {
+                    line.push(*byte).ok();
+                    if !line.is_full() {
+                        continue;
+                    }
+                }
+            }
+            len += format_uart_line(&mut fmt_buffer[len..], format, mark, line).len();
+            line.clear();
+        }
+        if received_len == 0 && !line.is_empty() {
+            len += format_uart_line(&mut fmt_buffer[len..], format, mark, line).len();
+            line.clear();
+        }
+
+        from_utf8(&fmt_buffer[..len]).unwrap_or_default()
+    }
+
+    // None if the event belongs to a pin this client does not watch
+    pub fn format_pin_event<'b>(
+        &mut self,
+        event: RushPinEvent,
+        fmt_buffer: &'b mut [u8],
+    ) -> Option<&'b str> {
+        let (format, mark) = (self.format, self.mark_events);
+        let mut text_buffer = [0u8; 128];
+        let response = match event {
+            RushPinEvent::Changed { pin, state } if self.is_watching(pin) => {
+                format_pin_state(&mut text_buffer, pin, state)
+            }
+            // both edges of the pulse, so clients see it like any other change
+            RushPinEvent::Pulse { pin, state } if self.is_watching(pin) => {
+                let edge = format_pin_state(&mut text_buffer, pin, !state);
+                let mut len = format_event(fmt_buffer, format, mark, "watch", &edge).len();
+                let edge = format_pin_state(&mut text_buffer, pin, state);
+                len += format_event(&mut fmt_buffer[len..], format, mark, "watch", &edge).len();
+                return Some(from_utf8(&fmt_buffer[..len]).unwrap_or_default());
+            }
+            RushPinEvent::Touched {
+                pin,
+                value,
+                touched,
+            } if self.is_watching(pin) => RushResponse::new(fmt_truncate(
+                &mut text_buffer,
+                format_args!(
+                    "touch.{} = {} ({})\n",
+                    pin,
+                    value,
+                    if touched { "touched" } else { "released" }
+                ),
+            ))
+            .id(RushId::Pin("touch", pin))
+            .value(RushValue::Number(value as i64))
+            .field("touched", RushValue::Bool(touched)),
+            RushPinEvent::Stopped { pin, reason } if self.is_watching(pin) => {
+                self.unwatch(pin);
+                format_stopped(
+                    &mut text_buffer,
+                    reason,
+                    format_args!("stopped watching gpio.{}", pin),
+                )
+                .id(RushId::Pin("gpio", pin))
+            }
+            RushPinEvent::TouchStopped { pin, reason } if self.is_watching(pin) => {
+                self.unwatch(pin);
+                format_stopped(
+                    &mut text_buffer,
+                    reason,
+                    format_args!("stopped watching touch.{}", pin),
+                )
+                .id(RushId::Pin("touch", pin))
+            }
+            _ => return None,
+        };
+        Some(format_event(fmt_buffer, format, mark, "watch", &response))
+    }
+
+    pub fn format_lagged<'b>(&self, count: u64, fmt_buffer: &'b mut [u8]) -> &'b str {
+        let mut text_buffer = [0u8; 64];
+        let response = RushResponse::new(fmt_truncate(
+            &mut text_buffer,
+            format_args!("warning: missed {} pin events\n", count),
+        ))
+        .field("missed", RushValue::Number(count as i64));
+        format_event(
+            fmt_buffer,
+            self.format,
+            self.mark_events,
+            "watch",
+            &response,
+        )
+    }
+}
+
+// `gpio.[pin] = [0 or 1]` - how watched and shouted pins report their level
+fn format_pin_state<'b>(fmt_buffer: &'b mut [u8], pin: u8, state: bool) -> RushResponse<'b> {
+    RushResponse::new(fmt_truncate(
+        fmt_buffer,
+        format_args!("gpio.{} = {}\n", pin, state as u8),
+    ))
+    .id(RushId::Pin("gpio", pin))
+    .value(RushValue::Number(state as i64))
+}
+
+// `uart: [line]` - the line is escaped, so every byte is sent
+fn format_uart_line<'b>(
+    fmt_buffer: &'b mut [u8],
+    format: RushFormat,
+    mark: bool,
+    line: &[u8],
+) -> &'b str {
+    // an escaped byte takes up to four characters, and the line is in the response twice
+    let mut text_buffer = [0u8; 8 * UART_LINE_LEN + 16];
+    let (value, rest) = fmt_split(&mut text_buffer, format_args!("{}", EscapedBytes(line)));
+    let message = fmt_truncate(rest, format_args!("uart: {}\n", value));
+    let response = RushResponse::new(message).value(RushValue::Text(value));
+    format_event(fmt_buffer, format, mark, "uart", &response)
+}
+
+// printable ascii is sent as is, every other byte as \xNN - the devices behind a uart do not always send utf8
+struct EscapedBytes<'a>(&'a [u8]);
+
+impl core::fmt::Display for EscapedBytes<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for byte in self.0 {
+            match byte {
+                b' '..=b'~' => write!(f, "{}", *byte as char)?,
+                _ => write!(f, "\\x{:02x}", byte)?,
+            }
+        }
+        Ok(())
+    }
+}
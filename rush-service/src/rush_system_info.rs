@@ -0,0 +1,240 @@
+use core::ptr::{addr_of, read_volatile, write_volatile};
+use core::str::from_utf8;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use embassy_sync::blocking_mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant};
+use esp32s3_hal::clock::Clocks;
+use stackfmt::fmt_truncate;
+
+use crate::rush_error::RushError;
+use crate::rush_response::{RushResponse, RushValue};
+use crate::rush_wifi::{wifi_status, RushWifiStatus};
+
+const FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// the unused part of the stack is filled with this at boot - the first overwritten word marks the high-water mark
+const STACK_PAINT: u32 = 0x7275_7368; // "rush"
+
+// stays unpainted below the stack pointer of init(), so interrupts and register spills during painting are safe
+const STACK_PAINT_MARGIN: usize = 1024;
+
+// esp-idf's conversion for the default measuring range of -10 to 80°C, in 1/10000°C
+const TEMPERATURE_PER_STEP: i32 = 4386;
+const TEMPERATURE_OFFSET: i32 = 205_200;
+// a conversion takes a few hundred microseconds
+const TEMPERATURE_TIMEOUT: Duration = Duration::from_millis(10);
+
+// more than esp-wifi sets aside for its heap, so the search always ends at the real limit
+const HEAP_PROBE_LIMIT: usize = 128 * 1024;
+
+static CPU_MHZ: AtomicU32 = AtomicU32::new(0);
+// held while probing the heap, so no allocation of the wifi driver can run into a probe
+static HEAP_PROBE: blocking_mutex::Mutex<CriticalSectionRawMutex, ()> =
+    blocking_mutex::Mutex::new(());
+static TEMPERATURE_SENSOR_ON: AtomicBool = AtomicBool::new(false);
+
+// bounds of the main stack from the linker script - it grows down from start to end
+extern "C" {
+    static _stack_start_cpu0: u32;
+    static _stack_end_cpu0: u32;
+}
+
+// the allocator of the esp-wifi heap - exported under the c names, as the wifi blobs call it
+extern "C" {
+    fn malloc(size: usize) -> *mut u8;
+    fn free(ptr: *mut u8);
+}
+
+// has to run first thing in main(), while the stack is still shallow
+pub fn init(clocks: &Clocks) {
+    CPU_MHZ.store(clocks.cpu_clock.to_MHz(), Ordering::Relaxed);
+
+    let local = 0usize;
+    let paint_end = stack_pointer_estimate(&local) - STACK_PAINT_MARGIN;
+    let mut word = unsafe { addr_of!(_stack_end_cpu0) } as usize;
+    while word < paint_end {
+        // safety: the words between the end of the stack and the current stack pointer are not in use yet
+        unsafe { write_volatile(word as *mut u32, STACK_PAINT) };
+        word += 4;
+    }
+}
+
+// the address of a local variable is the best estimate of the stack pointer rust offers
+#[inline(never)]
+fn stack_pointer_estimate(local: &usize) -> usize {
+    local as *const usize as usize
+}
+
+// bytes of the main stack that were ever in use, and the size of the stack
+fn stack_usage() -> (usize, usize) {
+    let start = unsafe { addr_of!(_stack_start_cpu0) } as usize;
+    let end = unsafe { addr_of!(_stack_end_cpu0) } as usize;
+    let mut word = end;
+    // safety: only reads within the stack bounds
+    while word < start && unsafe { read_volatile(word as *const u32) } == STACK_PAINT {
+        word += 4;
+    }
+    (start - word, start - end)
+}
+
+// bytes in the largest block the esp-wifi heap can still hand out - esp-wifi does not report how much is free,
+// so this searches for the biggest allocation that succeeds and frees every probe right away
+fn largest_free_heap_block() -> usize {
+    HEAP_PROBE.lock(|_| {
+        let (mut low, mut high) = (0, HEAP_PROBE_LIMIT);
+        while low < high {
+            let size = (low + high + 1) / 2;
+            // safety: every block that is handed out is freed again before the next probe
+            let block = unsafe { malloc(size) };
+            if block.is_null() {
+                high = size - 1;
+            } else {
+                unsafe { free(block) };
+                low = size;
+            }
+        }
+        low
+    })
+}
+
+// esp32s3-hal has no driver for the internal temperature sensor - this follows temperature_sensor_ll.h of esp-idf.
+// returns tenths of a degree celsius
+fn chip_temperature() -> Result<i32, RushError> {
+    // safety: the sens peripheral is owned by the adc driver, but that never touches these registers
+    let sens = unsafe { &*esp32s3_hal::peripherals::SENS::PTR };
+    if !TEMPERATURE_SENSOR_ON.swap(true, Ordering::Relaxed) {
+        sens.sar_peri_clk_gate_conf
+            .modify(|_, w| w.tsens_clk_en().set_bit());
+        sens.sar_tctrl.modify(|_, w| {
+            w.tsens_power_up_force()
+                .set_bit()
+                .tsens_power_up()
+                .set_bit()
+        });
+        sens.sar_tctrl2.modify(|_, w| w.tsens_xpd_force().set_bit());
+    }
+
+    sens.sar_tctrl.modify(|_, w| w.tsens_dump_out().set_bit());
+    let deadline = Instant::now() + TEMPERATURE_TIMEOUT;
+    let ready = loop {
+        if sens.sar_tctrl.read().tsens_ready().bit_is_set() {
+            break true;
+        }
+        if Instant::now() >= deadline {
+            break false;
+        }
+    };
+    let raw = sens.sar_tctrl.read().tsens_out().bits() as i32;
+    sens.sar_tctrl.modify(|_, w| w.tsens_dump_out().clear_bit());
+
+    match ready {
+        true => Ok((raw * TEMPERATURE_PER_STEP - TEMPERATURE_OFFSET) / 1000),
+        false => Err(RushError::Timeout(
+            "temperature sensor did not finish a conversion",
+        )),
+    }
+}
+
+pub fn format_system_info<'b>(fmt_buffer: &'b mut [u8]) -> RushResponse<'b> {
+    let uptime = Instant::now().as_secs();
+    // a sensor that does not answer must not hide the rest - uptime and firmware tell whether the board rebooted
+    let temperature = match chip_temperature() {
+        Ok(temperature) => Some(temperature),
+        Err(err) => {
+            log::warn!("could not read chip temperature: {}", err);
+            None
+        }
+    };
+    let (stack_used, stack_size) = stack_usage();
+
+    let mut len = fmt_truncate(
+        fmt_buffer,
+        format_args!(
+            "firmware = rush {}\nuptime = {}d {:02}:{:02}:{:02}\n",
+            FIRMWARE_VERSION,
+            uptime / 86_400,
+            uptime / 3600 % 24,
+            uptime / 60 % 60,
+            uptime % 60,
+        ),
+    )
+    .len();
+    len += match temperature {
+        Some(temperature) => fmt_truncate(
+            &mut fmt_buffer[len..],
+            format_args!(
+                "temperature = {}{}.{}°C\n",
+                if temperature < 0 { "-" } else { "" },
+                temperature.abs() / 10,
+                temperature.abs() % 10,
+            ),
+        ),
+        None => fmt_truncate(
+            &mut fmt_buffer[len..],
+            format_args!("temperature = unavailable\n"),
+        ),
+    }
+    .len();
+    len += fmt_truncate(
+        &mut fmt_buffer[len..],
+        format_args!("cpu = {}MHz\n", CPU_MHZ.load(Ordering::Relaxed)),
+    )
+    .len();
+    len += fmt_truncate(
+        &mut fmt_buffer[len..],
+        format_args!(
+            "stack = {} of {} bytes used at most\n",
+            stack_used, stack_size
+        ),
+    )
+    .len();
+    // rush itself never allocates - the heap belongs to the wifi driver
+    let heap_free = largest_free_heap_block();
+    len += fmt_truncate(
+        &mut fmt_buffer[len..],
+        format_args!("heap = {} bytes free in the largest block\n", heap_free),
+    )
+    .len();
+    let wifi = wifi_status();
+    len += match wifi {
+        RushWifiStatus::AccessPoint { stations } => fmt_truncate(
+            &mut fmt_buffer[len..],
+            format_args!("wifi = access point, {} stations connected\n", stations),
+        ),
+        RushWifiStatus::Station { connected } => fmt_truncate(
+            &mut fmt_buffer[len..],
+            format_args!(
+                "wifi = station, {}\n",
+                if connected {
+                    "connected"
+                } else {
+                    "not connected"
+                }
+            ),
+        ),
+    }
+    .len();
+
+    let cpu_mhz = CPU_MHZ.load(Ordering::Relaxed);
+    let mut response = RushResponse::new(from_utf8(&fmt_buffer[..len]).unwrap_or_default())
+        .field("firmware", RushValue::Text(FIRMWARE_VERSION))
+        .field("uptime_s", RushValue::Number(uptime as i64));
+    // left out while the sensor is unavailable
+    if let Some(temperature) = temperature {
+        response = response.field("temperature_c", RushValue::Decimal(temperature as i64, 1));
+    }
+    let response = response
+        .field("cpu_mhz", RushValue::Number(cpu_mhz as i64))
+        .field("stack_used", RushValue::Number(stack_used as i64))
+        .field("stack_size", RushValue::Number(stack_size as i64))
+        .field("heap_free_block", RushValue::Number(heap_free as i64));
+    match wifi {
+        RushWifiStatus::AccessPoint { stations } => response
+            .field("wifi", RushValue::Text("access point"))
+            .field("stations", RushValue::Number(stations as i64)),
+        RushWifiStatus::Station { connected } => response
+            .field("wifi", RushValue::Text("station"))
+            .field("connected", RushValue::Bool(connected)),
+    }
+}
@@ -0,0 +1,119 @@
+use crate::rush_error::RushError;
+
+use core::ptr::{read_volatile, write_volatile};
+
+// esp32s3-hal has no touch driver yet - this drives the touch sensor registers directly, the way
+// touch_sensor_ll.h of esp-idf does it. every read puts the pad into touch mode through its rtcio register first,
+// like touch_pad_io_init() does
+
+// touch channel n is gpio n - channel 0 is internal and cannot be read
+pub const TOUCH_PINS: core::ops::RangeInclusive<u8> = 1..=14;
+
+const MEASURE_CYCLES: u16 = 500; // charge and discharge cycles per measurement - more is slower, but less noisy
+const MEASURE_TIMEOUT_LOOPS: u32 = 100_000;
+const DATA_MASK: u32 = 0x3f_ffff; // the raw value has 22 bits
+
+// fields of the rtc_io touch_padN registers - the pac only has them per pad, these work for all 15
+const PAD_FUN_IE: u32 = 1 << 13; // input enable
+const PAD_FUN_SEL: u32 = 0b11 << 17; // rtc function - 0 is the analog function
+const PAD_MUX_SEL: u32 = 1 << 19; // connects the pad to the rtc instead of the gpio matrix
+const PAD_RUE: u32 = 1 << 27; // pull-up
+const PAD_RDE: u32 = 1 << 28; // pull-down
+
+pub struct RushTouchSensor {
+    _private: (),
+}
+
+impl RushTouchSensor {
+    // the touch fsm is only started by software, so it does not run while no pad is read
+    pub fn new() -> Self {
+        // safety: the rtc and sens peripherals are owned by the hal drivers, but those never touch these registers
+        let rtc_cntl = unsafe { &*esp32s3_hal::peripherals::RTC_CNTL::PTR };
+        rtc_cntl.touch_ctrl1.modify(|_, w| unsafe {
+            w.touch_meas_num().bits(MEASURE_CYCLES);
+            w.touch_sleep_cycles().bits(0x0f)
+        });
+        rtc_cntl.touch_ctrl2.modify(|_, w| unsafe {
+            w.touch_drefh().bits(3); // 2.7V
+            w.touch_drefl().bits(0); // 0.5V
+            w.touch_drange().bits(3); // 1V
+            w.touch_xpd_bias().set_bit();
+            w.touch_clkgate_en().set_bit();
+            w.touch_start_force().set_bit()
+        });
+
+        RushTouchSensor { _private: () }
+    }
+
+    // measures a single pad - the raw value goes up while the pad is touched
+    pub fn read(&mut self, channel: u8) -> Result<u32, RushError> {
+        if !TOUCH_PINS.contains(&channel) {
+            return Err(RushError::NoSuchPin(
+                "pin has no touch channel - only gpio.1 to gpio.14 can be touched",
+            ));
+        }
+
+        configure_pad(channel);
+
+        // safety: see new()
+        let rtc_cntl = unsafe { &*esp32s3_hal::peripherals::RTC_CNTL::PTR };
+        let sens = unsafe { &*esp32s3_hal::peripherals::SENS::PTR };
+
+        rtc_cntl
+            .touch_scan_ctrl
+            .modify(|_, w| unsafe { w.touch_scan_pad_map().bits(1 << channel) });
+        sens.sar_touch_conf.modify(|_, w| unsafe {
+            w.touch_outen().bits(1 << channel);
+            w.touch_data_sel().bits(0) // raw value, not the filtered one
+        });
+
+        rtc_cntl
+            .touch_ctrl2
+            .modify(|_, w| w.touch_start_en().clear_bit());
+        rtc_cntl
+            .touch_ctrl2
+            .modify(|_, w| w.touch_start_en().set_bit());
+        let mut loops = 0;
+        while sens
+            .sar_touch_chn_st
+            .read()
+            .touch_meas_done()
+            .bit_is_clear()
+        {
+            loops += 1;
+            if loops > MEASURE_TIMEOUT_LOOPS {
+                return Err(RushError::Timeout("touch measurement timed out"));
+            }
+        }
+
+        // the status registers of the 14 channels follow each other, so the pac's per channel registers are skipped
+        let status = sens.sar_touch_status1.as_ptr();
+        // safety: channel is checked against TOUCH_PINS above
+        let data = unsafe { read_volatile(status.add(channel as usize - 1)) };
+        Ok(data & DATA_MASK)
+    }
+}
+
+// hands the pad to the rtc without input buffer or pulls - either of them would load the pad and shift its value
+fn configure_pad(channel: u8) {
+    // safety: see RushTouchSensor::new() - rtc_io is only used for the pads of the touch pins, which the pin
+    // manager has switched to analog mode
+    let sens = unsafe { &*esp32s3_hal::peripherals::SENS::PTR };
+    let rtc_io = unsafe { &*esp32s3_hal::peripherals::RTC_IO::PTR };
+
+    // the rtcio registers only take writes while their clock runs
+    sens.sar_peri_clk_gate_conf
+        .modify(|_, w| w.iomux_clk_en().set_bit());
+
+    // the touch_padN registers follow each other like the status registers do
+    let pads = rtc_io.touch_pad0.as_ptr();
+    // safety: channel is checked against TOUCH_PINS by the caller
+    unsafe {
+        let pad = pads.add(channel as usize);
+        let value = read_volatile(pad);
+        write_volatile(
+            pad,
+            value & !(PAD_FUN_IE | PAD_FUN_SEL | PAD_RUE | PAD_RDE) | PAD_MUX_SEL,
+        );
+    }
+}
@@ -1,20 +1,81 @@
+use core::sync::atomic::{AtomicBool, Ordering};
 use embassy_executor::_export::StaticCell;
 use embassy_executor::{SpawnError, Spawner};
-use embassy_net::{Config, Ipv4Address, Ipv4Cidr, Stack, StackResources, StaticConfig};
+use embassy_futures::select::{select, Either};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{
+    Config, IpAddress, IpEndpoint, Ipv4Address, Ipv4Cidr, Stack, StackResources, StaticConfig,
+};
+use embassy_time::{Duration, Instant, Timer};
 use embedded_svc::wifi::{Configuration, Wifi};
 use esp32s3_hal::clock::Clocks;
+use esp32s3_hal::macros::ram;
 use esp32s3_hal::peripherals::TIMG1;
 use esp32s3_hal::system::RadioClockControl;
 use esp32s3_hal::Rng;
 use esp_backtrace as _;
-use esp_wifi::wifi::{WifiController, WifiDevice, WifiMode};
+use esp_wifi::wifi::{WifiController, WifiDevice, WifiEvent, WifiMode};
 
-static STACK_RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+// one socket per client plus some headroom
+static STACK_RESOURCES: StaticCell<StackResources<8>> = StaticCell::new();
 static NETWORK_STACK: StaticCell<Stack<WifiDevice>> = StaticCell::new();
 
+// in access point mode the dhcp server hands out the rest of the board's /24 network
+const DHCP_FIRST_HOST: u8 = 1;
+const DHCP_LAST_HOST: u8 = 254;
+const DHCP_MAX_LEASES: usize = 16;
+const DHCP_LEASE_TIME: Duration = Duration::from_secs(2 * 60 * 60);
+
+// a station that could not connect this often gives up and reboots into access point mode
+const STATION_CONNECT_ATTEMPTS: u8 = 5;
+
+// reported by the sys command - the mode is set once at boot
+static ACCESS_POINT_MODE: AtomicBool = AtomicBool::new(false);
+static STATION_CONNECTED: AtomicBool = AtomicBool::new(false);
+
+pub enum RushWifiStatus {
+    AccessPoint { stations: u8 },
+    Station { connected: bool },
+}
+
+pub fn wifi_status() -> RushWifiStatus {
+    if !ACCESS_POINT_MODE.load(Ordering::Relaxed) {
+        return RushWifiStatus::Station {
+            connected: STATION_CONNECTED.load(Ordering::Relaxed),
+        };
+    }
+
+    // the driver keeps track of its stations anyway, so they are not counted here
+    let mut station_list: esp_wifi::binary::include::wifi_sta_list_t =
+        unsafe { core::mem::zeroed() };
+    let stations =
+        match unsafe { esp_wifi::binary::include::esp_wifi_ap_get_sta_list(&mut station_list) } {
+            0 => station_list.num as u8, // ESP_OK
+            _ => 0,                      // the access point is not started yet
+        };
+    RushWifiStatus::AccessPoint { stations }
+}
+
+// survives a software reset but not a power cycle - tells the next boot to skip station mode
+#[ram(rtc_fast, uninitialized)]
+static mut ACCESS_POINT_FALLBACK: u32 = 0;
+const ACCESS_POINT_FALLBACK_MAGIC: u32 = 0x7275_7368; // "rush"
+
+// true exactly once after run_wifi() gave up on station mode - the flag is cleared while reading it
+pub fn take_access_point_fallback() -> bool {
+    unsafe {
+        let fallback = ACCESS_POINT_FALLBACK == ACCESS_POINT_FALLBACK_MAGIC;
+        ACCESS_POINT_FALLBACK = 0;
+        fallback
+    }
+}
+
 pub struct RushWifi {
     wifi_controller: WifiController<'static>,
     network_stack: &'static Stack<WifiDevice<'static>>,
+    wifi_mode: WifiMode,
+    access_point_address: Ipv4Address,
+    station_retry_interval: Duration,
 }
 
 impl RushWifi {
@@ -25,6 +86,8 @@ impl RushWifi {
         clocks: &Clocks,
         wifi_peripheral: esp32s3_hal::radio::Wifi,
         wifi_configuration: &Configuration,
+        access_point_address: Ipv4Address,
+        station_retry_interval: Duration,
     ) -> Self {
         // generate random network stack seed (before moving rng) as it is
         // "strongly recommended" for it to change for each boot
@@ -36,8 +99,22 @@ impl RushWifi {
             panic!("esp_wifi::initialize failed: {:?}", e);
         }
 
+        // a client configuration joins an existing network and asks its dhcp server for an address,
+        // an access point configuration opens the "rush" network with a static address
+        let (wifi_mode, config) = match wifi_configuration {
+            Configuration::Client(_) => (WifiMode::Sta, Config::Dhcp(Default::default())),
+            _ => (
+                WifiMode::Ap,
+                Config::Static(StaticConfig {
+                    address: Ipv4Cidr::new(access_point_address, 24),
+                    gateway: Some(access_point_address),
+                    dns_servers: Default::default(),
+                }),
+            ),
+        };
+
         let (wifi_interface, mut wifi_controller) =
-            esp_wifi::wifi::new_with_mode(wifi_peripheral, WifiMode::Ap);
+            esp_wifi::wifi::new_with_mode(wifi_peripheral, wifi_mode);
         if let Err(e) = wifi_controller.set_configuration(wifi_configuration) {
             panic!(
                 "esp_wifi::wifi::WifiController.set_configuration failed: {:?}",
@@ -45,12 +122,6 @@ impl RushWifi {
             );
         }
 
-        let config = Config::Static(StaticConfig {
-            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 2, 1), 24),
-            gateway: Some(Ipv4Address::new(192, 168, 2, 1)),
-            dns_servers: Default::default(),
-        });
-
         // initialize network stack
         let network_stack = &*NETWORK_STACK.init(Stack::new(
             wifi_interface,
@@ -62,15 +133,33 @@ impl RushWifi {
         RushWifi {
             wifi_controller,
             network_stack,
+            wifi_mode,
+            access_point_address,
+            station_retry_interval,
         }
     }
 
     pub fn start(self, embassy_spawner: &Spawner) -> &'static Stack<WifiDevice<'static>> {
-        if let Err(SpawnError::Busy) =
-            embassy_spawner.spawn(run_wifi(self.wifi_controller, &self.network_stack))
-        {
+        // stations get their address from the network they join - access points have to hand them out
+        let is_access_point = matches!(self.wifi_mode, WifiMode::Ap);
+        ACCESS_POINT_MODE.store(is_access_point, Ordering::Relaxed);
+
+        if let Err(SpawnError::Busy) = embassy_spawner.spawn(run_wifi(
+            self.wifi_controller,
+            &self.network_stack,
+            self.wifi_mode,
+            self.station_retry_interval,
+        )) {
             panic!("could not spawn embassy task: run_wifi - seems like it is already running? this should not be possible...");
         }
+        if is_access_point {
+            if let Err(SpawnError::Busy) = embassy_spawner.spawn(run_dhcp_server(
+                self.network_stack,
+                self.access_point_address,
+            )) {
+                panic!("could not spawn embassy task: run_dhcp_server - seems like it is already running? this should not be possible...");
+            }
+        }
         self.network_stack
     }
 }
@@ -79,6 +168,8 @@ impl RushWifi {
 async fn run_wifi(
     mut controller: WifiController<'static>,
     network_stack: &'static Stack<WifiDevice<'static>>,
+    wifi_mode: WifiMode,
+    station_retry_interval: Duration,
 ) -> ! {
     log::info!("starting wifi");
     if let Err(e) = controller.start().await {
@@ -88,5 +179,341 @@ async fn run_wifi(
         );
     }
     log::info!("wifi started!");
+
+    if let WifiMode::Sta = wifi_mode {
+        // neither of them ever returns
+        match select(
+            keep_station_connected(controller, network_stack, station_retry_interval),
+            network_stack.run(),
+        )
+        .await
+        {
+            Either::First(never) | Either::Second(never) => never,
+        }
+    }
     network_stack.run().await
 }
+
+async fn keep_station_connected(
+    mut controller: WifiController<'static>,
+    network_stack: &'static Stack<WifiDevice<'static>>,
+    retry_interval: Duration,
+) -> ! {
+    let mut failed_attempts = 0;
+    let mut was_connected = false;
+    loop {
+        match controller.connect().await {
+            Ok(()) => {
+                log::info!("connected to wifi network - waiting for dhcp...");
+                while !network_stack.is_config_up() {
+                    Timer::after(Duration::from_millis(500)).await;
+                }
+                if let Some(config) = network_stack.config() {
+                    log::info!("got address {} via dhcp", config.address);
+                }
+
+                was_connected = true;
+                STATION_CONNECTED.store(true, Ordering::Relaxed);
+                controller.wait_for_event(WifiEvent::StaDisconnected).await;
+                STATION_CONNECTED.store(false, Ordering::Relaxed);
+                log::warn!("lost connection to wifi network - reconnecting...");
+            }
+            Err(e) => {
+                log::error!("could not connect to wifi network: {:?}", e);
+
+                // only a network that was never reachable is a reason to fall back - a connection that
+                // dropped later is retried for as long as it takes
+                failed_attempts += 1;
+                if !was_connected && failed_attempts >= STATION_CONNECT_ATTEMPTS {
+                    log::error!("giving up on station mode - rebooting as access point...");
+                    unsafe {
+                        ACCESS_POINT_FALLBACK = ACCESS_POINT_FALLBACK_MAGIC;
+                    }
+                    esp32s3_hal::reset::software_reset();
+                }
+            }
+        }
+        Timer::after(retry_interval).await;
+    }
+}
+
+#[embassy_executor::task]
+async fn run_dhcp_server(
+    network_stack: &'static Stack<WifiDevice<'static>>,
+    server_address: Ipv4Address,
+) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 2048];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 2048];
+    let mut socket = UdpSocket::new(
+        network_stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(DHCP_SERVER_PORT) {
+        panic!("could not bind dhcp server socket: {:?}", e);
+    }
+    log::info!("dhcp server started");
+
+    let mut dhcp_server = DhcpServer::new(server_address);
+    let mut request = [0u8; DHCP_MESSAGE_SIZE];
+    let mut response = [0u8; DHCP_MESSAGE_SIZE];
+    loop {
+        let len = match socket.recv_from(&mut request).await {
+            Ok((len, _)) => len,
+            Err(e) => {
+                log::warn!("dhcp server could not receive: {:?}", e);
+                continue;
+            }
+        };
+
+        // clients without an address can not be reached directly, so every answer is a broadcast
+        if let Some(response_len) = dhcp_server.handle_message(&request[..len], &mut response) {
+            let broadcast = IpEndpoint::new(IpAddress::v4(255, 255, 255, 255), DHCP_CLIENT_PORT);
+            if let Err(e) = socket.send_to(&response[..response_len], broadcast).await {
+                log::warn!("dhcp server could not send: {:?}", e);
+            }
+        }
+    }
+}
+
+// minimal dhcp server (rfc 2131) - leases addresses of the access point network without a gateway or
+// name server, so clients do not try to reach the internet through the board
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+const DHCP_MESSAGE_SIZE: usize = 576;
+const DHCP_OPTIONS_OFFSET: usize = 240;
+const DHCP_MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+const DHCP_BOOTREQUEST: u8 = 1;
+const DHCP_BOOTREPLY: u8 = 2;
+
+const DHCP_OPTION_PAD: u8 = 0;
+const DHCP_OPTION_SUBNET_MASK: u8 = 1;
+const DHCP_OPTION_REQUESTED_ADDRESS: u8 = 50;
+const DHCP_OPTION_LEASE_TIME: u8 = 51;
+const DHCP_OPTION_MESSAGE_TYPE: u8 = 53;
+const DHCP_OPTION_SERVER_ID: u8 = 54;
+const DHCP_OPTION_END: u8 = 255;
+
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_REQUEST: u8 = 3;
+const DHCP_DECLINE: u8 = 4;
+const DHCP_ACK: u8 = 5;
+const DHCP_NAK: u8 = 6;
+const DHCP_RELEASE: u8 = 7;
+
+// owner of a lease on an address a client declined - no client has it, so the address stays blocked until it expires
+const DHCP_DECLINED_MAC: [u8; 6] = [0; 6];
+
+#[derive(Clone, Copy)]
+struct DhcpLease {
+    mac: [u8; 6],
+    host: u8, // last octet of the leased address
+    expires: Instant,
+}
+
+struct DhcpServer {
+    server_address: Ipv4Address,
+    leases: [Option<DhcpLease>; DHCP_MAX_LEASES],
+}
+
+impl DhcpServer {
+    fn new(server_address: Ipv4Address) -> Self {
+        DhcpServer {
+            server_address,
+            leases: [None; DHCP_MAX_LEASES],
+        }
+    }
+
+    fn is_free(&self, host: u8, mac: &[u8; 6]) -> bool {
+        let now = Instant::now();
+        (DHCP_FIRST_HOST..=DHCP_LAST_HOST).contains(&host)
+            && host != self.server_address.0[3]
+            && self
+                .leases
+                .iter()
+                .flatten()
+                .all(|lease| lease.host != host || lease.mac == *mac || lease.expires <= now)
+    }
+
+    // the client's previous address if it is still free for it, the address it asked for if that is free,
+    // or the lowest free address otherwise - an expired lease may have been given to another client since
+    fn find_address(&self, mac: &[u8; 6], requested_host: Option<u8>) -> Option<u8> {
+        if let Some(lease) = self
+            .leases
+            .iter()
+            .flatten()
+            .find(|lease| lease.mac == *mac && self.is_free(lease.host, mac))
+        {
+            return Some(lease.host);
+        }
+        if let Some(host) = requested_host.filter(|host| self.is_free(*host, mac)) {
+            return Some(host);
+        }
+        (DHCP_FIRST_HOST..=DHCP_LAST_HOST).find(|host| self.is_free(*host, mac))
+    }
+
+    // false if the address is taken or there is no room left for another lease
+    fn commit(&mut self, mac: &[u8; 6], host: u8) -> bool {
+        if !self.is_free(host, mac) {
+            return false;
+        }
+        let now = Instant::now();
+        let slot = self
+            .leases
+            .iter()
+            .position(|lease| matches!(lease, Some(lease) if lease.mac == *mac))
+            .or_else(|| {
+                self.leases
+                    .iter()
+                    .position(|lease| !matches!(lease, Some(lease) if lease.expires > now))
+            });
+        match slot {
+            None => false,
+            Some(slot) => {
+                self.leases[slot] = Some(DhcpLease {
+                    mac: *mac,
+                    host,
+                    expires: now + DHCP_LEASE_TIME,
+                });
+                true
+            }
+        }
+    }
+
+    fn release(&mut self, mac: &[u8; 6]) {
+        for lease in self.leases.iter_mut() {
+            if matches!(lease, Some(l) if l.mac == *mac) {
+                *lease = None;
+            }
+        }
+    }
+
+    // the client found its address in use by someone else - that address must not be offered again right away
+    fn decline(&mut self, mac: &[u8; 6]) {
+        for lease in self.leases.iter_mut().flatten() {
+            if lease.mac == *mac {
+                lease.mac = DHCP_DECLINED_MAC;
+            }
+        }
+    }
+
+    // writes the answer to `request` into `response` and returns its length - None if there is nothing to answer
+    fn handle_message(
+        &mut self,
+        request: &[u8],
+        response: &mut [u8; DHCP_MESSAGE_SIZE],
+    ) -> Option<usize> {
+        if request.len() < DHCP_OPTIONS_OFFSET
+            || request[0] != DHCP_BOOTREQUEST
+            || request[236..240] != DHCP_MAGIC_COOKIE
+        {
+            return None;
+        }
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(&request[28..34]);
+
+        let mut message_type = None;
+        let mut requested_address = None;
+        let mut server_id = None;
+        let mut options = &request[DHCP_OPTIONS_OFFSET..];
+        loop {
+            match options {
+                [DHCP_OPTION_PAD, rest @ ..] => options = rest,
+                [code, len, rest @ ..]
+                    if *code != DHCP_OPTION_END && rest.len() >= *len as usize =>
+                {
+                    let (value, rest) = rest.split_at(*len as usize);
+                    match (*code, value) {
+                        (DHCP_OPTION_MESSAGE_TYPE, [message]) => message_type = Some(*message),
+                        (DHCP_OPTION_REQUESTED_ADDRESS, [a, b, c, d]) => {
+                            requested_address = Some(Ipv4Address::new(*a, *b, *c, *d))
+                        }
+                        (DHCP_OPTION_SERVER_ID, [a, b, c, d]) => {
+                            server_id = Some(Ipv4Address::new(*a, *b, *c, *d))
+                        }
+                        _ => (),
+                    }
+                    options = rest;
+                }
+                _ => break, // end option or truncated message
+            }
+        }
+
+        // only addresses of the access point network can be leased
+        let requested_host = requested_address.and_then(|address| {
+            let [a, b, c, host] = address.0;
+            let [ap_a, ap_b, ap_c, _] = self.server_address.0;
+            if [a, b, c] == [ap_a, ap_b, ap_c] {
+                Some(host)
+            } else {
+                None
+            }
+        });
+
+        let (reply_type, host) = match message_type? {
+            DHCP_DISCOVER => (DHCP_OFFER, self.find_address(&mac, requested_host)?),
+            DHCP_REQUEST => {
+                if matches!(server_id, Some(id) if id != self.server_address) {
+                    return None; // the client chose another server
+                }
+                // clients renewing their lease send their address as ciaddr instead of an option
+                let requested_host = requested_host.or_else(|| match request[12..16] {
+                    [0, 0, 0, 0] => None,
+                    [_, _, _, host] => Some(host),
+                    _ => None,
+                });
+                match requested_host {
+                    Some(host) if self.commit(&mac, host) => (DHCP_ACK, host),
+                    _ => (DHCP_NAK, 0),
+                }
+            }
+            DHCP_DECLINE => {
+                self.decline(&mac);
+                return None;
+            }
+            DHCP_RELEASE => {
+                self.release(&mac);
+                return None;
+            }
+            _ => return None,
+        };
+
+        response.fill(0);
+        response[0] = DHCP_BOOTREPLY;
+        response[1..3].copy_from_slice(&request[1..3]); // hardware type and address length
+        response[4..8].copy_from_slice(&request[4..8]); // transaction id
+        response[10..12].copy_from_slice(&request[10..12]); // flags
+        if reply_type != DHCP_NAK {
+            let [a, b, c, _] = self.server_address.0;
+            response[16..20].copy_from_slice(&[a, b, c, host]); // your address
+        }
+        response[28..44].copy_from_slice(&request[28..44]); // client hardware address
+        response[236..240].copy_from_slice(&DHCP_MAGIC_COOKIE);
+
+        let mut len = DHCP_OPTIONS_OFFSET;
+        let mut push_option = |code: u8, value: &[u8]| {
+            response[len] = code;
+            response[len + 1] = value.len() as u8;
+            response[len + 2..len + 2 + value.len()].copy_from_slice(value);
+            len += 2 + value.len();
+        };
+        push_option(DHCP_OPTION_MESSAGE_TYPE, &[reply_type]);
+        push_option(DHCP_OPTION_SERVER_ID, &self.server_address.0);
+        if reply_type != DHCP_NAK {
+            push_option(
+                DHCP_OPTION_LEASE_TIME,
+                &(DHCP_LEASE_TIME.as_secs() as u32).to_be_bytes(),
+            );
+            push_option(DHCP_OPTION_SUBNET_MASK, &[255, 255, 255, 0]);
+        }
+        response[len] = DHCP_OPTION_END;
+
+        Some(len + 1)
+    }
+}